@@ -0,0 +1,492 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Binary data in JSON strings, as RFC 4648 Base64 or hex. Both directions
+// work in chunks, so that a firmware image or a certificate never has to be
+// held in memory in its decoded form.
+
+use core::fmt::{self, Write};
+use json::{JsonStr, JsonStrChars};
+
+static BASE64: &'static [u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+static BASE64_URL: &'static [u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+static HEX: &'static [u8; 16] = b"0123456789abcdef";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryEncoding {
+    /// Base64 with `+` and `/`, padded with `=`.
+    Base64,
+    /// Base64 with `-` and `_`, without padding as in JWTs. Padding is still
+    /// accepted when decoding.
+    Base64Url,
+    /// Two hex digits per byte, lowercase when encoding and in either case
+    /// when decoding.
+    Hex,
+}
+
+impl BinaryEncoding {
+    fn alphabet(&self) -> &'static [u8] {
+        match *self {
+            BinaryEncoding::Base64 => BASE64,
+            BinaryEncoding::Base64Url => BASE64_URL,
+            BinaryEncoding::Hex => HEX,
+        }
+    }
+
+    // Number of bits in a character.
+    fn bits(&self) -> u32 {
+        if *self == BinaryEncoding::Hex { 4 } else { 6 }
+    }
+
+    fn value(&self, c: char) -> Option<u32> {
+        let c = match c {
+            'A'...'F' if *self == BinaryEncoding::Hex => c as u8 - b'A' + b'a',
+            '\0'...'\x7f' => c as u8,
+            _ => return None,
+        };
+        self.alphabet().iter().position(|&digit| digit == c).map(|value| value as u32)
+    }
+}
+
+/// Encodes bytes given in chunks, writing the characters without quotes.
+/// See `JsonWriter::begin_binary()` to write them as a JSON string value.
+pub struct BinaryEncoder {
+    encoding: BinaryEncoding,
+    // Bytes waiting for a complete group of 3, in Base64.
+    pending: u32,
+    len: usize,
+}
+
+impl BinaryEncoder {
+    pub fn new(encoding: BinaryEncoding) -> Self {
+        BinaryEncoder {
+            encoding: encoding,
+            pending: 0,
+            len: 0,
+        }
+    }
+
+    // Writes the characters for the `bits` high bits of `group`.
+    fn write_group<W: Write>(&self, out: &mut W, group: u32, bits: u32) -> fmt::Result {
+        let alphabet = self.encoding.alphabet();
+        let mut shift = 24;
+        while shift > 24 - bits {
+            shift -= 6;
+            out.write_char(alphabet[(group >> shift) as usize & 0x3f] as char)?;
+        }
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&mut self, out: &mut W, data: &[u8]) -> fmt::Result {
+        for &byte in data {
+            if self.encoding == BinaryEncoding::Hex {
+                out.write_char(HEX[(byte >> 4) as usize] as char)?;
+                out.write_char(HEX[(byte & 0xf) as usize] as char)?;
+                continue;
+            }
+            self.pending = (self.pending << 8) | byte as u32;
+            self.len += 1;
+            if self.len == 3 {
+                let group = self.pending;
+                self.write_group(out, group, 24)?;
+                self.pending = 0;
+                self.len = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the last characters, and the padding if any.
+    pub fn finish<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        if self.len == 0 {
+            return Ok(());
+        }
+        let (group, len) = (self.pending << (8 * (3 - self.len as u32)), self.len);
+        self.write_group(out, group, 6 * (len as u32 + 1))?;
+        if self.encoding == BinaryEncoding::Base64 {
+            out.write_str(if len == 1 { "==" } else { "=" })?;
+        }
+        self.pending = 0;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryErrorKind {
+    /// A character that isn't part of the encoding's alphabet.
+    InvalidCharacter,
+    /// Padding in the wrong place or of the wrong length, or unused bits
+    /// that aren't zero at the end of the data.
+    InvalidPadding,
+    /// The data ends in the middle of a byte.
+    Truncated,
+    /// The output given to `BinaryDecoder::feed()` is shorter than the
+    /// fragment.
+    OutputTooSmall,
+}
+
+impl fmt::Display for BinaryErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BinaryErrorKind::InvalidCharacter => "invalid character",
+            BinaryErrorKind::InvalidPadding => "invalid padding",
+            BinaryErrorKind::Truncated => "truncated data",
+            BinaryErrorKind::OutputTooSmall => "output too small",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BinaryError {
+    pub kind: BinaryErrorKind,
+    /// Position of the character in the decoded string, counted in
+    /// characters.
+    pub offset: usize,
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at character {}", self.kind, self.offset)
+    }
+}
+
+/// Decodes binary data from a string read by a tokenizer, in chunks of the
+/// caller's choosing. The string is either in memory, for instance in a
+/// document read from flash, or given in fragments to `feed()`. Escape
+/// sequences such as `\/` are decoded first, and padding is optional with
+/// Base64.
+pub struct BinaryDecoder<'a> {
+    chars: JsonStrChars<'a>,
+    encoding: BinaryEncoding,
+    // Bits decoded but not written yet.
+    bits: u32,
+    len: u32,
+    // Number of characters read, padding included.
+    offset: usize,
+    padding: usize,
+    done: bool,
+}
+
+impl<'a> BinaryDecoder<'a> {
+    pub fn new(value: JsonStr<'a>, encoding: BinaryEncoding) -> Self {
+        BinaryDecoder {
+            chars: value.chars(),
+            encoding: encoding,
+            bits: 0,
+            len: 0,
+            offset: 0,
+            padding: 0,
+            done: false,
+        }
+    }
+
+    /// A decoder for a string given in fragments to `feed()`, such as the
+    /// ones of `JsonStreamTokenizer::string_fragments()`.
+    pub fn fragments(encoding: BinaryEncoding) -> BinaryDecoder<'static> {
+        BinaryDecoder::new(JsonStr::new(""), encoding)
+    }
+
+    fn error(&self, kind: BinaryErrorKind) -> BinaryError {
+        BinaryError {
+            kind: kind,
+            offset: self.offset,
+        }
+    }
+
+    // Checks what is left once all the characters have been read.
+    fn end(&mut self) -> Result<(), BinaryError> {
+        let data = self.offset - self.padding;
+        if self.encoding == BinaryEncoding::Hex {
+            if data % 2 != 0 {
+                return Err(self.error(BinaryErrorKind::Truncated));
+            }
+        } else {
+            if data % 4 == 1 {
+                return Err(self.error(BinaryErrorKind::Truncated));
+            }
+            if self.padding > 0 && self.offset % 4 != 0 {
+                return Err(self.error(BinaryErrorKind::InvalidPadding));
+            }
+            if self.bits != 0 {
+                return Err(self.error(BinaryErrorKind::InvalidPadding));
+            }
+        }
+        self.done = true;
+        Ok(())
+    }
+
+    // Decodes a character, returning the byte it completes if any.
+    fn decode(&mut self, c: char) -> Result<Option<u8>, BinaryError> {
+        if c == '=' && self.encoding != BinaryEncoding::Hex {
+            // Only 2 or 3 characters of a group can be followed by padding.
+            let data = (self.offset - self.padding) % 4;
+            if data < 2 || self.padding == 4 - data {
+                return Err(self.error(BinaryErrorKind::InvalidPadding));
+            }
+            self.padding += 1;
+            self.offset += 1;
+            return Ok(None);
+        }
+        let value = match self.encoding.value(c) {
+            Some(value) => value,
+            None => return Err(self.error(BinaryErrorKind::InvalidCharacter)),
+        };
+        if self.padding > 0 {
+            return Err(self.error(BinaryErrorKind::InvalidPadding));
+        }
+        self.offset += 1;
+        self.bits = (self.bits << self.encoding.bits()) | value;
+        self.len += self.encoding.bits();
+        if self.len < 8 {
+            return Ok(None);
+        }
+        self.len -= 8;
+        let byte = (self.bits >> self.len) as u8;
+        self.bits &= (1 << self.len) - 1;
+        Ok(Some(byte))
+    }
+
+    /// Decodes bytes into `out`, returning how many were written. This
+    /// returns 0 once all the data has been decoded.
+    pub fn read(&mut self, out: &mut [u8]) -> Result<usize, BinaryError> {
+        let mut written = 0;
+        // Each character completes at most one byte.
+        while written < out.len() && !self.done {
+            let c = match self.chars.next() {
+                Some(c) => c,
+                None => {
+                    self.end()?;
+                    break;
+                }
+            };
+            if let Some(byte) = self.decode(c)? {
+                out[written] = byte;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Decodes the next fragment of the string into `out`, returning how many
+    /// bytes were written. An `out` as long as `fragment.raw()` is always
+    /// large enough.
+    pub fn feed(&mut self, fragment: JsonStr, out: &mut [u8]) -> Result<usize, BinaryError> {
+        // Each character completes at most one byte, and takes at least one
+        // byte of the raw text.
+        if out.len() < fragment.raw().len() {
+            return Err(self.error(BinaryErrorKind::OutputTooSmall));
+        }
+        let mut written = 0;
+        for c in fragment.chars() {
+            if let Some(byte) = self.decode(c)? {
+                out[written] = byte;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Checks the end of the data once all the fragments have been given to
+    /// `feed()`.
+    pub fn finish(&mut self) -> Result<(), BinaryError> {
+        self.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use binary::{BinaryDecoder, BinaryEncoder, BinaryEncoding, BinaryError, BinaryErrorKind};
+    use collections::String;
+    use collections::string::ToString;
+    use collections::vec::Vec;
+    use core::cmp;
+    use json::{JsonStr, JsonStreamTokenizer, JsonToken, JsonTokenizer};
+    use writer::{JsonWriteError, JsonWriter};
+
+    // RFC 4648 section 10.
+    static VECTORS: [(&'static str, &'static str, &'static str); 7] =
+        [("", "", ""),
+         ("f", "Zg==", "66"),
+         ("fo", "Zm8=", "666f"),
+         ("foo", "Zm9v", "666f6f"),
+         ("foob", "Zm9vYg==", "666f6f62"),
+         ("fooba", "Zm9vYmE=", "666f6f6261"),
+         ("foobar", "Zm9vYmFy", "666f6f626172")];
+
+    fn encode(data: &[u8], encoding: BinaryEncoding, chunk_size: usize) -> String {
+        let mut out = String::new();
+        let mut encoder = BinaryEncoder::new(encoding);
+        for chunk in data.chunks(chunk_size) {
+            encoder.write(&mut out, chunk).unwrap();
+        }
+        encoder.finish(&mut out).unwrap();
+        out
+    }
+
+    fn decode(text: &str,
+              encoding: BinaryEncoding,
+              chunk_size: usize)
+              -> Result<Vec<u8>, BinaryError> {
+        let mut decoder = BinaryDecoder::new(JsonStr::new(text), encoding);
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 8];
+        loop {
+            match decoder.read(&mut buffer[..chunk_size])? {
+                0 => return Ok(data),
+                len => data.extend_from_slice(&buffer[..len]),
+            }
+        }
+    }
+
+    #[test]
+    fn rfc_vectors() {
+        for &(data, base64, hex) in VECTORS.iter() {
+            for chunk_size in 1..5 {
+                let data = data.as_bytes();
+                assert_eq!(encode(data, BinaryEncoding::Base64, chunk_size), base64);
+                assert_eq!(encode(data, BinaryEncoding::Base64Url, chunk_size),
+                           base64.trim_right_matches('='));
+                assert_eq!(encode(data, BinaryEncoding::Hex, chunk_size), hex);
+                assert_eq!(decode(base64, BinaryEncoding::Base64, chunk_size).unwrap(), data);
+                assert_eq!(decode(base64.trim_right_matches('='),
+                                  BinaryEncoding::Base64,
+                                  chunk_size)
+                               .unwrap(),
+                           data);
+                assert_eq!(decode(hex, BinaryEncoding::Hex, chunk_size).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn alphabets() {
+        let data = [0xfb, 0xff, 0xbf, 0x00, 0xab];
+        assert_eq!(encode(&data, BinaryEncoding::Base64, 8), "+/+/AKs=");
+        assert_eq!(encode(&data, BinaryEncoding::Base64Url, 8), "-_-_AKs");
+        assert_eq!(decode("-_-_AKs=", BinaryEncoding::Base64Url, 8).unwrap(), data);
+        assert_eq!(decode("fbffBF00aB", BinaryEncoding::Hex, 8).unwrap(), data);
+        // `/` is often escaped in JSON.
+        assert_eq!(decode(r#"+\/+\/AKs="#, BinaryEncoding::Base64, 8).unwrap(), data);
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [("Zm9v-A==", BinaryEncoding::Base64, BinaryErrorKind::InvalidCharacter, 4),
+                     ("Zm9v/A==", BinaryEncoding::Base64Url, BinaryErrorKind::InvalidCharacter, 4),
+                     ("Zm9 v", BinaryEncoding::Base64, BinaryErrorKind::InvalidCharacter, 3),
+                     ("Zm9vY", BinaryEncoding::Base64, BinaryErrorKind::Truncated, 5),
+                     ("Z===", BinaryEncoding::Base64, BinaryErrorKind::InvalidPadding, 1),
+                     ("Zg=", BinaryEncoding::Base64, BinaryErrorKind::InvalidPadding, 3),
+                     ("Zm8==", BinaryEncoding::Base64, BinaryErrorKind::InvalidPadding, 4),
+                     ("Zg=a", BinaryEncoding::Base64, BinaryErrorKind::InvalidPadding, 3),
+                     ("Zh==", BinaryEncoding::Base64, BinaryErrorKind::InvalidPadding, 4),
+                     ("666", BinaryEncoding::Hex, BinaryErrorKind::Truncated, 3),
+                     ("6g", BinaryEncoding::Hex, BinaryErrorKind::InvalidCharacter, 1),
+                     ("66==", BinaryEncoding::Hex, BinaryErrorKind::InvalidCharacter, 2)];
+
+        for &(text, encoding, kind, offset) in cases.iter() {
+            assert_eq!(decode(text, encoding, 8),
+                       Err(BinaryError {
+                           kind: kind,
+                           offset: offset,
+                       }));
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let firmware = (0..200).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut writer = JsonWriter::new(String::new());
+        writer.begin_object().unwrap();
+        writer.key("image").unwrap();
+        writer.begin_binary(BinaryEncoding::Base64).unwrap();
+        assert_eq!(writer.key("size"), Err(JsonWriteError::UnfinishedBinary));
+        for chunk in firmware.chunks(64) {
+            writer.binary_chunk(chunk).unwrap();
+        }
+        writer.end_binary().unwrap();
+        assert_eq!(writer.end_binary(), Err(JsonWriteError::Unbalanced));
+        writer.key("sha").unwrap();
+        writer.binary(&[0xde, 0xad], BinaryEncoding::Hex).unwrap();
+        writer.end_object().unwrap();
+        let text = writer.finish().unwrap();
+        assert!(text.ends_with(r#"=","sha":"dead"}"#));
+
+        let mut tokenizer = JsonTokenizer::new(&text);
+        tokenizer.next_token().unwrap();
+        tokenizer.next_token().unwrap();
+        let image = match tokenizer.next_token().unwrap() {
+            JsonToken::String(image) => image,
+            token => panic!("Unexpected token {:?}", token),
+        };
+        let mut decoder = BinaryDecoder::new(image, BinaryEncoding::Base64);
+        let mut flash = [0u8; 16];
+        let mut decoded = Vec::new();
+        loop {
+            match decoder.read(&mut flash).unwrap() {
+                0 => break,
+                len => decoded.extend_from_slice(&flash[..len]),
+            }
+        }
+        assert_eq!(decoded, firmware);
+    }
+
+    #[test]
+    fn stream_fragments() {
+        let firmware = (0..600).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut writer = JsonWriter::new(String::new());
+        writer.begin_object().unwrap();
+        writer.key("image").unwrap();
+        writer.binary(&firmware, BinaryEncoding::Base64).unwrap();
+        writer.key("sha").unwrap();
+        writer.binary(&[0xde, 0xad], BinaryEncoding::Hex).unwrap();
+        writer.end_object().unwrap();
+        // Escaped like some servers do.
+        let text = writer.finish().unwrap().replace("/", "\\/");
+
+        // The image is 25 times longer than the buffer.
+        let mut buffer = [0u8; 32];
+        let mut tokenizer = JsonStreamTokenizer::new(&mut buffer).string_fragments(true);
+        let mut decoder = BinaryDecoder::fragments(BinaryEncoding::Base64);
+        let mut flash = [0u8; 32];
+        let mut decoded = Vec::new();
+        let mut fragments = 0;
+        let mut sha = String::new();
+        let mut input = text.as_bytes();
+        loop {
+            match tokenizer.next_token().unwrap() {
+                Some(JsonToken::StringFragment(fragment)) => {
+                    let len = decoder.feed(fragment, &mut flash).unwrap();
+                    decoded.extend_from_slice(&flash[..len]);
+                    fragments += 1;
+                }
+                Some(JsonToken::EndString) => decoder.finish().unwrap(),
+                Some(JsonToken::String(value)) => sha = value.to_string(),
+                Some(JsonToken::Done) => break,
+                Some(_) => {}
+                None => {
+                    let fed = tokenizer.feed(&input[..cmp::min(7, input.len())]);
+                    input = &input[fed..];
+                    if input.is_empty() {
+                        tokenizer.finish();
+                    }
+                }
+            }
+        }
+        assert_eq!(decoded, firmware);
+        assert!(fragments > 25);
+        assert_eq!(sha, "dead");
+
+        let mut decoder = BinaryDecoder::fragments(BinaryEncoding::Hex);
+        assert_eq!(decoder.feed(JsonStr::new("6"), &mut flash), Ok(0));
+        assert_eq!(decoder.feed(JsonStr::new("6f"), &mut flash), Ok(1));
+        assert_eq!(decoder.finish().unwrap_err().kind, BinaryErrorKind::Truncated);
+        assert_eq!(decoder.feed(JsonStr::new("6f6"), &mut flash[..2]).unwrap_err().kind,
+                   BinaryErrorKind::OutputTooSmall);
+    }
+}
@@ -0,0 +1,274 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// RFC 8785 JSON Canonicalization Scheme: a unique serialization of a
+// document, so that both ends of a signed exchange hash the same bytes.
+
+use core::cmp::Ordering;
+use core::f64;
+use core::fmt::Write;
+use core::str;
+use dom::JsonValue;
+use json::{JsonStr, JsonStrChars, JsonToken};
+use writer::{write_json_str, JsonWriteError, SliceWriter};
+
+// The UTF-16 code units of a string, which object keys are sorted by.
+struct Utf16<'a> {
+    chars: JsonStrChars<'a>,
+    low: Option<u16>,
+}
+
+impl<'a> Iterator for Utf16<'a> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        if let Some(low) = self.low.take() {
+            return Some(low);
+        }
+        match self.chars.next() {
+            Some(c) if c as u32 >= 0x10000 => {
+                let c = c as u32 - 0x10000;
+                self.low = Some(0xdc00 | (c & 0x3ff) as u16);
+                Some(0xd800 | (c >> 10) as u16)
+            }
+            Some(c) => Some(c as u16),
+            None => None,
+        }
+    }
+}
+
+fn compare_keys(a: JsonStr, b: JsonStr) -> Ordering {
+    let a = Utf16 {
+        chars: a.chars(),
+        low: None,
+    };
+    let b = Utf16 {
+        chars: b.chars(),
+        low: None,
+    };
+    a.cmp(b)
+}
+
+// Splits a number written with `{:e}` into its digits and its exponent.
+fn split_exponent(text: &str, digits: &mut [u8; 24]) -> (usize, i32) {
+    let e = text.find('e').unwrap_or(text.len());
+    let mut len = 0;
+    for &c in text[..e].as_bytes().iter().filter(|&&c| c != b'.') {
+        digits[len] = c;
+        len += 1;
+    }
+    (len, text[e + 1..].parse::<i32>().unwrap_or(0))
+}
+
+// Whether `digits * 10^-places` is a double.
+fn is_double(digits: u64, places: i32) -> bool {
+    let mut odd = digits;
+    while odd != 0 && odd % 2 == 0 {
+        odd /= 2;
+    }
+    for _ in 0..places {
+        if odd % 5 != 0 {
+            return false;
+        }
+        odd /= 5;
+    }
+    for _ in places..0 {
+        odd = match odd.checked_mul(5) {
+            Some(odd) => odd,
+            None => return false,
+        };
+    }
+    odd < 1 << 53
+}
+
+/// Writes a number the way ECMAScript's `Number.prototype.toString()` does,
+/// as required by RFC 8785.
+pub fn write_canonical_number<W: Write>(out: &mut W, value: f64) -> Result<(), JsonWriteError> {
+    if value != value || value == f64::INFINITY || value == f64::NEG_INFINITY {
+        return Err(JsonWriteError::InvalidNumber);
+    }
+    if value == 0.0 {
+        // Negative zero included.
+        out.write_char('0')?;
+        return Ok(());
+    }
+    if value < 0.0 {
+        out.write_char('-')?;
+    }
+    let value = if value < 0.0 { -value } else { value };
+
+    // The shortest digits that read back as `value`.
+    let mut digits = [0u8; 24];
+    let mut buffer = [0u8; 32];
+    let (k, mut exponent) = {
+        let mut text = SliceWriter::new(&mut buffer);
+        write!(text, "{:e}", value)?;
+        split_exponent(text.as_str(), &mut digits)
+    };
+
+    // When `value` is exactly halfway between two of them, the even one has
+    // to be used, which the formatter doesn't guarantee.
+    if digits[k - 1] % 2 == 1 {
+        let mut text = SliceWriter::new(&mut buffer);
+        write!(text, "{:.*e}", k, value)?;
+        let mut more = [0u8; 24];
+        let (_, more_exponent) = split_exponent(text.as_str(), &mut more);
+        let midpoint = more[..k + 1].iter().fold(0, |n, &c| n * 10 + (c - b'0') as u64);
+        if more[k] == b'5' && is_double(midpoint, k as i32 - more_exponent) &&
+           text.as_str().parse::<f64>() == Ok(value) {
+            if more[..k] != digits[..k] {
+                // `value` is above the even digits.
+                digits[..k].copy_from_slice(&more[..k]);
+                exponent = more_exponent;
+            } else if digits[k - 1] != b'9' {
+                digits[k - 1] += 1;
+            }
+        }
+    }
+
+    // Only ASCII digits have been written.
+    let digits = unsafe { str::from_utf8_unchecked(&digits[..k]) };
+    let (first, rest) = digits.split_at(1);
+    // With `k` digits, the value is `0.digits * 10^n`.
+    let k = k as i32;
+    let n = exponent + 1;
+    if k <= n && n <= 21 {
+        out.write_str(digits)?;
+        for _ in k..n {
+            out.write_char('0')?;
+        }
+    } else if 0 < n && n <= 21 {
+        let (integer, fraction) = digits.split_at(n as usize);
+        write!(out, "{}.{}", integer, fraction)?;
+    } else if -6 < n && n <= 0 {
+        out.write_str("0.")?;
+        for _ in n..0 {
+            out.write_char('0')?;
+        }
+        out.write_str(digits)?;
+    } else {
+        out.write_str(first)?;
+        if !rest.is_empty() {
+            write!(out, ".{}", rest)?;
+        }
+        write!(out, "e{}{}", if n > 0 { '+' } else { '-' }, (n - 1).abs())?;
+    }
+    Ok(())
+}
+
+/// Writes `value` in its RFC 8785 canonical form: no whitespace, members
+/// sorted by the UTF-16 code units of their names, minimal string escapes and
+/// numbers formatted like ECMAScript does. All numbers are IEEE 754 doubles,
+/// so integers above 2^53 may lose precision. Fails with `DuplicateKey` if
+/// an object has two members with the same name.
+pub fn write_canonical<W: Write>(out: &mut W, value: JsonValue) -> Result<(), JsonWriteError> {
+    match value.token() {
+        JsonToken::StartObject => {
+            out.write_char('{')?;
+            // Members are written in order by finding the smallest name
+            // after the previous one, so that no memory is needed.
+            let mut previous: Option<JsonStr> = None;
+            for i in 0..value.len() {
+                let mut next = None;
+                for (key, member) in value.members() {
+                    if let Some(previous) = previous {
+                        if compare_keys(key, previous) != Ordering::Greater {
+                            continue;
+                        }
+                    }
+                    let candidate = next;
+                    match candidate {
+                        Some((name, _)) => {
+                            match compare_keys(key, name) {
+                                Ordering::Less => next = Some((key, member)),
+                                Ordering::Equal => return Err(JsonWriteError::DuplicateKey),
+                                Ordering::Greater => {}
+                            }
+                        }
+                        None => next = Some((key, member)),
+                    }
+                }
+                let (key, member) = next.unwrap();
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write_json_str(out, key)?;
+                out.write_char(':')?;
+                write_canonical(out, member)?;
+                previous = Some(key);
+            }
+            out.write_char('}')?;
+        }
+        JsonToken::StartArray => {
+            out.write_char('[')?;
+            for (i, element) in value.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write_canonical(out, element)?;
+            }
+            out.write_char(']')?;
+        }
+        JsonToken::String(text) => write_json_str(out, text)?,
+        JsonToken::Integer(number) => write_canonical_number(out, number as f64)?,
+        JsonToken::Number(number) => write_canonical_number(out, number)?,
+        JsonToken::RawNumber(text) => {
+            match text.parse::<f64>() {
+                Ok(number) => write_canonical_number(out, number)?,
+                Err(_) => return Err(JsonWriteError::InvalidNumber),
+            }
+        }
+        JsonToken::True => out.write_str("true")?,
+        JsonToken::False => out.write_str("false")?,
+        _ => out.write_str("null")?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use canonical::{write_canonical, write_canonical_number};
+    use collections::String;
+    use core::f64;
+    use dom::{JsonNode, JsonValue};
+    use json::JsonTokenizer;
+    use writer::JsonWriteError;
+
+    // Shared with the server: pairs of lines, a document and its canonical
+    // form.
+    static VECTORS: &'static str = include_str!("../testdata/canonical.txt");
+
+    fn canonicalize(text: &str) -> Result<String, JsonWriteError> {
+        let mut nodes = [JsonNode::new(); 64];
+        let value = JsonValue::parse(&mut JsonTokenizer::new(text), &mut nodes).unwrap();
+        let mut out = String::new();
+        write_canonical(&mut out, value).map(|_| out)
+    }
+
+    #[test]
+    fn shared_vectors() {
+        let mut lines = VECTORS.lines().filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let mut count = 0;
+        while let Some(input) = lines.next() {
+            let expected = lines.next().unwrap();
+            assert_eq!(canonicalize(input).unwrap(), expected);
+            count += 1;
+        }
+        assert!(count > 20);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(canonicalize(r#"{"a": 1, "b": 2, "a": 3}"#),
+                   Err(JsonWriteError::DuplicateKey));
+        let mut out = String::new();
+        assert_eq!(write_canonical_number(&mut out, f64::NAN),
+                   Err(JsonWriteError::InvalidNumber));
+        assert_eq!(write_canonical_number(&mut out, f64::NEG_INFINITY),
+                   Err(JsonWriteError::InvalidNumber));
+        write_canonical_number(&mut out, -0.0).unwrap();
+        assert_eq!(out, "0");
+    }
+}
@@ -0,0 +1,1375 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// RFC 8949 CBOR, a compact binary alternative to JSON. The tokenizer and the
+// writer mirror `JsonTokenizer` and `JsonWriter`, and the transcoding
+// functions convert between both formats one token at a time.
+
+use core::f64;
+use core::fmt::{self, Write};
+use core::mem;
+use core::str;
+use binary::BinaryEncoding;
+use json::{JsonError, JsonStr, JsonToken, JsonTokenizer};
+use stack::NestingStack;
+use writer::{JsonWriteError, JsonWriter, SliceWriter};
+
+/// Maximum number of nested maps and arrays in a CBOR document.
+pub const MAX_CBOR_DEPTH: usize = 32;
+
+/// Maximum length in bytes of the big integer mantissa of a decimal
+/// fraction, enough for any mantissa of up to 77 digits.
+pub const MAX_BIGNUM_LEN: usize = 32;
+
+/// A CBOR data item. Maps and arrays of definite and indefinite length are
+/// reported the same way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CborToken<'a> {
+    StartMap,
+    EndMap,
+    StartArray,
+    EndArray,
+    Text(&'a str),
+    Bytes(&'a [u8]),
+    /// An indefinite length text string, whose chunks follow as `Text`
+    /// tokens until `EndText`.
+    StartText,
+    EndText,
+    /// An indefinite length byte string, whose chunks follow as `Bytes`
+    /// tokens until `EndBytes`.
+    StartBytes,
+    EndBytes,
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Null,
+    Undefined,
+    /// Any other simple value.
+    Simple(u8),
+    /// A tag, which applies to the next data item.
+    Tag(u64),
+    Done,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CborErrorKind {
+    UnexpectedEof,
+    /// Reserved additional information, an indefinite length where it is
+    /// not allowed, or a chunk of an indefinite length string that isn't a
+    /// definite length string of the same type.
+    InvalidHeader,
+    /// A break outside of an indefinite length map or array, or in the
+    /// middle of a map entry.
+    UnexpectedBreak,
+    InvalidUtf8,
+    /// An integer outside of the range of `i64`.
+    NumberOverflow,
+    NestingTooDeep,
+}
+
+impl fmt::Display for CborErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            CborErrorKind::UnexpectedEof => "unexpected end of input",
+            CborErrorKind::InvalidHeader => "invalid data item header",
+            CborErrorKind::UnexpectedBreak => "unexpected break",
+            CborErrorKind::InvalidUtf8 => "invalid UTF-8 in text string",
+            CborErrorKind::NumberOverflow => "integer out of range",
+            CborErrorKind::NestingTooDeep => "too many nested maps and arrays",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CborError {
+    pub kind: CborErrorKind,
+    /// Byte offset of the data item in the input.
+    pub offset: usize,
+}
+
+impl fmt::Display for CborError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.kind, self.offset)
+    }
+}
+
+// An open map or array.
+#[derive(Clone, Copy)]
+struct Level {
+    map: bool,
+    indefinite: bool,
+    // Data items left, for definite lengths. Map entries count as two.
+    remaining: usize,
+    // Whether the next data item of a map is a key.
+    key_next: bool,
+}
+
+pub struct CborTokenizer<'a> {
+    buffer: &'a [u8],
+    pos: usize,
+    levels: [Level; MAX_CBOR_DEPTH],
+    depth: usize,
+    // Whether the last token returned is a map key.
+    key: bool,
+    // A tag was read, its data item has to follow.
+    tagged: bool,
+    // The major type of the indefinite length string being read.
+    chunked: Option<u8>,
+    done: bool,
+}
+
+impl<'a> CborTokenizer<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        let level = Level {
+            map: false,
+            indefinite: false,
+            remaining: 0,
+            key_next: false,
+        };
+        CborTokenizer {
+            buffer: buffer,
+            pos: 0,
+            levels: [level; MAX_CBOR_DEPTH],
+            depth: 0,
+            key: false,
+            tagged: false,
+            chunked: None,
+            done: false,
+        }
+    }
+
+    /// Byte offset of the next data item to read.
+    pub fn offset(&self) -> usize {
+        self.pos
+    }
+
+    /// Whether the last token returned is a map key, or the tag of one.
+    pub fn is_key(&self) -> bool {
+        self.key
+    }
+
+    fn error_at(&self, offset: usize, kind: CborErrorKind) -> CborError {
+        CborError {
+            kind: kind,
+            offset: offset,
+        }
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8], CborError> {
+        if len > self.buffer.len() - self.pos {
+            return Err(self.error_at(self.buffer.len(), CborErrorKind::UnexpectedEof));
+        }
+        let buffer = self.buffer;
+        self.pos += len;
+        Ok(&buffer[self.pos - len..self.pos])
+    }
+
+    // Reads the argument of a header, big endian.
+    fn read_argument(&mut self, info: u8) -> Result<u64, CborError> {
+        let len = match info {
+            0...23 => return Ok(info as u64),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Err(self.error_at(self.pos - 1, CborErrorKind::InvalidHeader)),
+        };
+        let bytes = self.read(len)?;
+        Ok(bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u64))
+    }
+
+    // Called once a complete data item has been read.
+    fn item_done(&mut self) {
+        if self.depth == 0 {
+            self.done = true;
+            return;
+        }
+        let level = &mut self.levels[self.depth - 1];
+        if !level.indefinite {
+            level.remaining -= 1;
+        }
+        level.key_next = level.map && !level.key_next;
+    }
+
+    fn open(&mut self, map: bool, info: u8, start: usize) -> Result<CborToken<'a>, CborError> {
+        if self.depth == MAX_CBOR_DEPTH {
+            return Err(self.error_at(start, CborErrorKind::NestingTooDeep));
+        }
+        let indefinite = info == 31;
+        let mut remaining = 0;
+        if !indefinite {
+            let count = self.read_argument(info)?;
+            // Each data item takes at least one byte.
+            let available = (self.buffer.len() - self.pos) as u64;
+            if count > available || (map && count * 2 > available) {
+                return Err(self.error_at(self.buffer.len(), CborErrorKind::UnexpectedEof));
+            }
+            remaining = if map { count as usize * 2 } else { count as usize };
+        }
+        self.levels[self.depth] = Level {
+            map: map,
+            indefinite: indefinite,
+            remaining: remaining,
+            key_next: map,
+        };
+        self.depth += 1;
+        Ok(if map {
+            CborToken::StartMap
+        } else {
+            CborToken::StartArray
+        })
+    }
+
+    fn close(&mut self) -> CborToken<'a> {
+        self.depth -= 1;
+        let map = self.levels[self.depth].map;
+        self.item_done();
+        if map {
+            CborToken::EndMap
+        } else {
+            CborToken::EndArray
+        }
+    }
+
+    // Reads a definite length string of the given major type.
+    fn read_string(&mut self,
+                   major: u8,
+                   info: u8,
+                   start: usize)
+                   -> Result<CborToken<'a>, CborError> {
+        let len = self.read_argument(info)?;
+        if len > (self.buffer.len() - self.pos) as u64 {
+            return Err(self.error_at(self.buffer.len(), CborErrorKind::UnexpectedEof));
+        }
+        let bytes = self.read(len as usize)?;
+        if major == 2 {
+            return Ok(CborToken::Bytes(bytes));
+        }
+        match str::from_utf8(bytes) {
+            Ok(text) => Ok(CborToken::Text(text)),
+            Err(_) => Err(self.error_at(start, CborErrorKind::InvalidUtf8)),
+        }
+    }
+
+    // Reads the next chunk of an indefinite length string, or its end.
+    fn read_chunk(&mut self, major: u8) -> Result<CborToken<'a>, CborError> {
+        let start = self.pos;
+        let header = self.read(1)?[0];
+        if header == 0xff {
+            self.chunked = None;
+            self.item_done();
+            return Ok(if major == 2 {
+                CborToken::EndBytes
+            } else {
+                CborToken::EndText
+            });
+        }
+        if header >> 5 != major || header & 0x1f == 31 {
+            return Err(self.error_at(start, CborErrorKind::InvalidHeader));
+        }
+        self.read_string(major, header & 0x1f, start)
+    }
+
+    pub fn next_token(&mut self) -> Result<CborToken<'a>, CborError> {
+        self.key = false;
+        if self.done {
+            return Ok(CborToken::Done);
+        }
+        if let Some(major) = self.chunked {
+            // The chunks of a key are part of it.
+            self.key = self.depth > 0 && self.levels[self.depth - 1].key_next;
+            return self.read_chunk(major);
+        }
+        if self.depth > 0 && !self.tagged {
+            let level = self.levels[self.depth - 1];
+            if !level.indefinite && level.remaining == 0 {
+                return Ok(self.close());
+            }
+            if level.indefinite && self.buffer.get(self.pos) == Some(&0xff) {
+                if level.map && !level.key_next {
+                    return Err(self.error_at(self.pos, CborErrorKind::UnexpectedBreak));
+                }
+                self.pos += 1;
+                return Ok(self.close());
+            }
+        }
+        self.key = self.depth > 0 && self.levels[self.depth - 1].key_next;
+        self.tagged = false;
+
+        let start = self.pos;
+        let header = self.read(1)?[0];
+        let (major, info) = (header >> 5, header & 0x1f);
+        if info == 31 && (major < 2 || major == 6) {
+            return Err(self.error_at(start, CborErrorKind::InvalidHeader));
+        }
+        let token = match major {
+            0 | 1 => {
+                let value = self.read_argument(info)?;
+                if value > i64::max_value() as u64 {
+                    return Err(self.error_at(start, CborErrorKind::NumberOverflow));
+                }
+                CborToken::Integer(if major == 0 {
+                    value as i64
+                } else {
+                    -1 - value as i64
+                })
+            }
+            2 | 3 if info == 31 => {
+                self.chunked = Some(major);
+                return Ok(if major == 2 {
+                    CborToken::StartBytes
+                } else {
+                    CborToken::StartText
+                });
+            }
+            2 | 3 => self.read_string(major, info, start)?,
+            4 | 5 => return self.open(major == 5, info, start),
+            6 => {
+                self.tagged = true;
+                return Ok(CborToken::Tag(self.read_argument(info)?));
+            }
+            _ => {
+                match info {
+                    20 => CborToken::Bool(false),
+                    21 => CborToken::Bool(true),
+                    22 => CborToken::Null,
+                    23 => CborToken::Undefined,
+                    24 => {
+                        let value = self.read(1)?[0];
+                        if value < 32 {
+                            return Err(self.error_at(start, CborErrorKind::InvalidHeader));
+                        }
+                        CborToken::Simple(value)
+                    }
+                    25 => {
+                        let bits = self.read_argument(info)? as u16;
+                        CborToken::Float(half_to_f64(bits))
+                    }
+                    26 => {
+                        let bits = self.read_argument(info)? as u32;
+                        CborToken::Float(unsafe { mem::transmute::<u32, f32>(bits) } as f64)
+                    }
+                    27 => {
+                        let bits = self.read_argument(info)?;
+                        CborToken::Float(unsafe { mem::transmute::<u64, f64>(bits) })
+                    }
+                    31 => return Err(self.error_at(start, CborErrorKind::UnexpectedBreak)),
+                    28...30 => return Err(self.error_at(start, CborErrorKind::InvalidHeader)),
+                    _ => CborToken::Simple(info),
+                }
+            }
+        };
+        self.item_done();
+        Ok(token)
+    }
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let sign = ((half as u64) >> 15) << 63;
+    let exponent = ((half >> 10) & 0x1f) as u64;
+    let mantissa = (half & 0x3ff) as u64;
+    let bits = match exponent {
+        0 => {
+            // Subnormal, `mantissa * 2^-24`.
+            let value = mantissa as f64 * 5.9604644775390625e-8;
+            unsafe { mem::transmute::<f64, u64>(value) }
+        }
+        31 => 0x7ff0000000000000 | (mantissa << 42),
+        _ => ((exponent + 1023 - 15) << 52) | (mantissa << 42),
+    };
+    unsafe { mem::transmute::<u64, f64>(sign | bits) }
+}
+
+// Returns the half precision encoding of `value`, if there is one.
+fn f32_to_half(value: f32) -> Option<u16> {
+    let bits = unsafe { mem::transmute::<f32, u32>(value) };
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7fffff;
+    if exponent == 0 {
+        // Zero, or too small.
+        return if mantissa == 0 { Some(sign) } else { None };
+    }
+    if exponent == 255 {
+        return if mantissa == 0 { Some(sign | 0x7c00) } else { None };
+    }
+    let exponent = exponent - 127;
+    if exponent >= -14 && exponent <= 15 && mantissa & 0x1fff == 0 {
+        return Some(sign | (((exponent + 15) as u16) << 10) | (mantissa >> 13) as u16);
+    }
+    if exponent >= -24 && exponent < -14 {
+        // A subnormal half, `full * 2^(exponent - 23)` being `m * 2^-24`.
+        let full = 0x800000 | mantissa;
+        let shift = -(exponent + 1) as u32;
+        if full & ((1 << shift) - 1) == 0 {
+            return Some(sign | (full >> shift) as u16);
+        }
+    }
+    None
+}
+
+// A decimal fraction, RFC 8949 section 3.4.4: `mantissa * 10^exponent`, the
+// mantissa being a big endian unsigned integer.
+#[derive(Clone, Copy, PartialEq)]
+struct DecimalFraction {
+    negative: bool,
+    mantissa: [u8; MAX_BIGNUM_LEN],
+    exponent: i64,
+}
+
+impl DecimalFraction {
+    // Parses a number in JSON syntax, returning `None` if it has too many
+    // digits.
+    fn parse(text: &str) -> Option<DecimalFraction> {
+        let bytes = text.as_bytes();
+        let mut number = DecimalFraction {
+            negative: bytes.first() == Some(&b'-'),
+            mantissa: [0; MAX_BIGNUM_LEN],
+            exponent: 0,
+        };
+        let mut pos = if number.negative { 1 } else { 0 };
+        let mut fraction = false;
+        let mut digits = 0;
+        while pos < bytes.len() {
+            match bytes[pos] {
+                b'.' if !fraction => fraction = true,
+                c @ b'0'...b'9' => {
+                    if !number.mul_add(10, c - b'0') {
+                        return None;
+                    }
+                    if fraction {
+                        number.exponent -= 1;
+                    }
+                    digits += 1;
+                }
+                b'e' | b'E' => break,
+                _ => return None,
+            }
+            pos += 1;
+        }
+        if digits == 0 {
+            return None;
+        }
+        if pos < bytes.len() {
+            let exponent = match text[pos + 1..].trim_left_matches('+').parse::<i64>() {
+                Ok(exponent) => exponent,
+                Err(_) => return None,
+            };
+            number.exponent = match number.exponent.checked_add(exponent) {
+                Some(exponent) => exponent,
+                None => return None,
+            };
+        }
+        Some(number)
+    }
+
+    // Sets the mantissa to `mantissa * factor + add`, returning false if it
+    // overflows.
+    fn mul_add(&mut self, factor: u32, add: u8) -> bool {
+        let mut carry = add as u32;
+        for byte in self.mantissa.iter_mut().rev() {
+            let value = *byte as u32 * factor + carry;
+            *byte = value as u8;
+            carry = value >> 8;
+        }
+        carry == 0
+    }
+
+    // Divides the mantissa by 10, returning the remainder.
+    fn div_10(&mut self) -> u8 {
+        let mut remainder = 0;
+        for byte in self.mantissa.iter_mut() {
+            let value = (remainder << 8) | *byte as u32;
+            *byte = (value / 10) as u8;
+            remainder = value % 10;
+        }
+        remainder as u8
+    }
+
+    fn is_zero(&self) -> bool {
+        self.mantissa.iter().all(|&byte| byte == 0)
+    }
+
+    // The mantissa without its leading zero bytes.
+    fn significant(&self) -> &[u8] {
+        let start = self.mantissa.iter().position(|&byte| byte != 0).unwrap_or(MAX_BIGNUM_LEN);
+        &self.mantissa[start..]
+    }
+
+    // Moves the trailing zeros of the mantissa into the exponent.
+    fn normalize(&mut self) {
+        if self.is_zero() {
+            self.exponent = 0;
+            return;
+        }
+        loop {
+            let mantissa = self.mantissa;
+            if self.div_10() != 0 {
+                self.mantissa = mantissa;
+                return;
+            }
+            self.exponent = self.exponent.saturating_add(1);
+        }
+    }
+
+    // Whether `value` is exactly this number, as far as their shortest
+    // decimal representation goes.
+    fn is_float(&self, value: f64) -> bool {
+        let mut buffer = [0u8; 32];
+        let mut out = SliceWriter::new(&mut buffer);
+        if write!(out, "{:e}", value).is_err() {
+            return false;
+        }
+        match DecimalFraction::parse(out.as_str()) {
+            Some(mut float) => {
+                let mut number = *self;
+                float.normalize();
+                number.normalize();
+                float == number
+            }
+            None => false,
+        }
+    }
+}
+
+// Written as a JSON number.
+impl fmt::Display for DecimalFraction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negative {
+            f.write_str("-")?;
+        }
+        // 10^77 < 2^256 < 10^78.
+        let mut digits = [0u8; 78];
+        let mut count = 0;
+        let mut rest = *self;
+        while count == 0 || !rest.is_zero() {
+            digits[count] = b'0' + rest.div_10();
+            count += 1;
+        }
+        for i in (0..count).rev() {
+            write!(f, "{}", digits[i] as char)?;
+        }
+        if self.exponent != 0 {
+            write!(f, "e{}", self.exponent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds a CBOR document into a fixed size buffer, checking its structure
+/// like `JsonWriter` does. Maps and arrays have an indefinite length, so
+/// that they can be written without knowing their size up front.
+pub struct CborWriter<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+    stack: NestingStack,
+    // A key has been written and its value is expected.
+    after_key: bool,
+    // The chunks of an indefinite length text string are being written.
+    chunked: bool,
+    done: bool,
+}
+
+impl<'b> CborWriter<'b> {
+    pub fn new(buffer: &'b mut [u8]) -> Self {
+        CborWriter {
+            buffer: buffer,
+            len: 0,
+            stack: NestingStack::new(),
+            after_key: false,
+            chunked: false,
+            done: false,
+        }
+    }
+
+    /// The encoded document, once it is complete.
+    pub fn finish(&self) -> Result<&[u8], JsonWriteError> {
+        if !self.done {
+            return Err(JsonWriteError::Incomplete);
+        }
+        Ok(&self.buffer[..self.len])
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), JsonWriteError> {
+        if bytes.len() > self.buffer.len() - self.len {
+            return Err(JsonWriteError::Write);
+        }
+        self.buffer[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+
+    // Writes a header with the shortest encoding of its argument.
+    fn header(&mut self, major: u8, argument: u64) -> Result<(), JsonWriteError> {
+        let (info, len) = if argument < 24 {
+            (argument as u8, 0)
+        } else if argument <= 0xff {
+            (24, 1)
+        } else if argument <= 0xffff {
+            (25, 2)
+        } else if argument <= 0xffffffff {
+            (26, 4)
+        } else {
+            (27, 8)
+        };
+        let mut bytes = [0u8; 9];
+        bytes[0] = (major << 5) | info;
+        for i in 0..len {
+            bytes[len - i] = (argument >> (8 * i)) as u8;
+        }
+        self.write(&bytes[..len + 1])
+    }
+
+    // Checks that a value can be written here.
+    fn before_value(&mut self) -> Result<(), JsonWriteError> {
+        if self.chunked {
+            return Err(JsonWriteError::UnfinishedString);
+        }
+        if self.done {
+            return Err(JsonWriteError::Complete);
+        }
+        if self.stack.depth > 0 && self.stack.in_object() {
+            if !self.after_key {
+                return Err(JsonWriteError::ExpectedKey);
+            }
+            self.after_key = false;
+        }
+        Ok(())
+    }
+
+    // Called once a complete value has been written.
+    fn value_done(&mut self) {
+        if self.stack.depth == 0 {
+            self.done = true;
+        }
+    }
+
+    fn begin(&mut self, map: bool) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        if self.stack.depth == MAX_CBOR_DEPTH || self.stack.push(map).is_err() {
+            return Err(JsonWriteError::NestingTooDeep);
+        }
+        self.write(&[if map { 0xbf } else { 0x9f }])
+    }
+
+    fn end(&mut self, map: bool) -> Result<(), JsonWriteError> {
+        if self.chunked {
+            return Err(JsonWriteError::UnfinishedString);
+        }
+        if self.stack.depth == 0 || self.stack.in_object() != map || self.after_key {
+            return Err(JsonWriteError::Unbalanced);
+        }
+        self.write(&[0xff])?;
+        self.stack.pop();
+        self.value_done();
+        Ok(())
+    }
+
+    pub fn begin_map(&mut self) -> Result<(), JsonWriteError> {
+        self.begin(true)
+    }
+
+    pub fn end_map(&mut self) -> Result<(), JsonWriteError> {
+        self.end(true)
+    }
+
+    pub fn begin_array(&mut self) -> Result<(), JsonWriteError> {
+        self.begin(false)
+    }
+
+    pub fn end_array(&mut self) -> Result<(), JsonWriteError> {
+        self.end(false)
+    }
+
+    /// Writes the key of the next map entry.
+    pub fn key(&mut self, key: &str) -> Result<(), JsonWriteError> {
+        if self.chunked {
+            return Err(JsonWriteError::UnfinishedString);
+        }
+        if self.stack.depth == 0 || !self.stack.in_object() || self.after_key {
+            return Err(JsonWriteError::UnexpectedKey);
+        }
+        self.header(3, key.len() as u64)?;
+        self.write(key.as_bytes())?;
+        self.after_key = true;
+        Ok(())
+    }
+
+    pub fn string(&mut self, value: &str) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.header(3, value.len() as u64)?;
+        self.write(value.as_bytes())?;
+        self.value_done();
+        Ok(())
+    }
+
+    /// Starts an indefinite length text string, whose chunks are given to
+    /// `string_chunk()` until `end_string()`.
+    pub fn begin_string(&mut self) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.write(&[0x7f])?;
+        self.chunked = true;
+        Ok(())
+    }
+
+    pub fn string_chunk(&mut self, value: &str) -> Result<(), JsonWriteError> {
+        if !self.chunked {
+            return Err(JsonWriteError::Unbalanced);
+        }
+        self.header(3, value.len() as u64)?;
+        self.write(value.as_bytes())
+    }
+
+    pub fn end_string(&mut self) -> Result<(), JsonWriteError> {
+        if !self.chunked {
+            return Err(JsonWriteError::Unbalanced);
+        }
+        self.write(&[0xff])?;
+        self.chunked = false;
+        self.value_done();
+        Ok(())
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.header(2, value.len() as u64)?;
+        self.write(value)?;
+        self.value_done();
+        Ok(())
+    }
+
+    fn integer_header(&mut self, value: i64) -> Result<(), JsonWriteError> {
+        if value >= 0 {
+            self.header(0, value as u64)
+        } else {
+            // -1 - value, without overflowing.
+            self.header(1, !(value as u64))
+        }
+    }
+
+    pub fn integer(&mut self, value: i64) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.integer_header(value)?;
+        self.value_done();
+        Ok(())
+    }
+
+    // Writes a decimal fraction, with a bignum mantissa if it doesn't fit in
+    // an `i64`.
+    fn decimal_fraction(&mut self, number: &DecimalFraction) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.write(&[0xc4, 0x82])?;
+        self.integer_header(number.exponent)?;
+        let mut magnitude = *number;
+        if number.negative && !number.is_zero() {
+            // -1 - magnitude.
+            for byte in magnitude.mantissa.iter_mut().rev() {
+                *byte = byte.wrapping_sub(1);
+                if *byte != 0xff {
+                    break;
+                }
+            }
+        }
+        let bignum = magnitude.significant();
+        if bignum.len() < 8 || (bignum.len() == 8 && bignum[0] < 0x80) {
+            let value = bignum.iter().fold(0, |value, &byte| (value << 8) | byte as u64);
+            self.header(if number.negative { 1 } else { 0 }, value)?;
+        } else {
+            self.header(6, if number.negative { 3 } else { 2 })?;
+            self.header(2, bignum.len() as u64)?;
+            self.write(bignum)?;
+        }
+        self.value_done();
+        Ok(())
+    }
+
+    /// Writes a float in the shortest encoding that keeps its value.
+    pub fn float(&mut self, value: f64) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        let single = value as f32;
+        if value != value {
+            self.write(&[0xf9, 0x7e, 0x00])?;
+        } else if single as f64 != value {
+            let bits = unsafe { mem::transmute::<f64, u64>(value) };
+            self.header(7, bits)?;
+        } else {
+            match f32_to_half(single) {
+                Some(half) => {
+                    self.write(&[0xf9, (half >> 8) as u8, half as u8])?;
+                }
+                None => {
+                    let bits = unsafe { mem::transmute::<f32, u32>(single) };
+                    self.write(&[0xfa])?;
+                    for i in 0..4 {
+                        self.write(&[(bits >> (24 - 8 * i)) as u8])?;
+                    }
+                }
+            }
+        }
+        self.value_done();
+        Ok(())
+    }
+
+    pub fn bool(&mut self, value: bool) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.write(&[if value { 0xf5 } else { 0xf4 }])?;
+        self.value_done();
+        Ok(())
+    }
+
+    pub fn null(&mut self) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.write(&[0xf6])?;
+        self.value_done();
+        Ok(())
+    }
+
+    // Writes the decoded text of a JSON string.
+    fn json_text(&mut self, text: JsonStr) -> Result<(), JsonWriteError> {
+        if let Some(text) = text.as_str() {
+            self.header(3, text.len() as u64)?;
+            return self.write(text.as_bytes());
+        }
+        let len = text.chars().map(|c| c.len_utf8()).sum::<usize>();
+        self.header(3, len as u64)?;
+        let mut bytes = [0u8; 4];
+        for c in text.chars() {
+            let len = c.encode_utf8(&mut bytes).len();
+            self.write(&bytes[..len])?;
+        }
+        Ok(())
+    }
+
+    /// Writes a JSON token, decoding strings. Objects become maps, strings
+    /// returned in fragments become indefinite length strings and `Done` is
+    /// ignored. Raw numbers that a float can't hold exactly become decimal
+    /// fractions, failing with `InvalidNumber` past 77 significant digits.
+    pub fn json_token(&mut self, token: JsonToken) -> Result<(), JsonWriteError> {
+        match token {
+            JsonToken::StartObject => self.begin_map(),
+            JsonToken::EndObject => self.end_map(),
+            JsonToken::StartArray => self.begin_array(),
+            JsonToken::EndArray => self.end_array(),
+            JsonToken::PropertyName(name) => {
+                if self.stack.depth == 0 || !self.stack.in_object() || self.after_key {
+                    return Err(JsonWriteError::UnexpectedKey);
+                }
+                self.json_text(name)?;
+                self.after_key = true;
+                Ok(())
+            }
+            JsonToken::String(value) => {
+                self.before_value()?;
+                self.json_text(value)?;
+                self.value_done();
+                Ok(())
+            }
+            JsonToken::StartString => self.begin_string(),
+            JsonToken::StringFragment(value) => {
+                if !self.chunked {
+                    return Err(JsonWriteError::Unbalanced);
+                }
+                self.json_text(value)
+            }
+            JsonToken::EndString => self.end_string(),
+            JsonToken::Integer(value) => self.integer(value),
+            JsonToken::Number(value) => self.float(value),
+            JsonToken::RawNumber(text) => {
+                let number = match DecimalFraction::parse(text) {
+                    Some(number) => number,
+                    None => return Err(JsonWriteError::InvalidNumber),
+                };
+                match text.parse::<f64>() {
+                    Ok(value) if number.is_float(value) => self.float(value),
+                    _ => self.decimal_fraction(&number),
+                }
+            }
+            JsonToken::Null => self.null(),
+            JsonToken::True => self.bool(true),
+            JsonToken::False => self.bool(false),
+            JsonToken::Done => Ok(()),
+        }
+    }
+}
+
+/// Error while converting between JSON and CBOR.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TranscodeError {
+    Json(JsonError),
+    Cbor(CborError),
+    Write(JsonWriteError),
+    /// A map key that isn't a text string, or a decimal fraction that isn't
+    /// an array of an integer exponent and an integer or bignum mantissa of
+    /// at most `MAX_BIGNUM_LEN` bytes.
+    NotJson,
+}
+
+impl From<JsonError> for TranscodeError {
+    fn from(err: JsonError) -> TranscodeError {
+        TranscodeError::Json(err)
+    }
+}
+
+impl From<CborError> for TranscodeError {
+    fn from(err: CborError) -> TranscodeError {
+        TranscodeError::Cbor(err)
+    }
+}
+
+impl From<JsonWriteError> for TranscodeError {
+    fn from(err: JsonWriteError) -> TranscodeError {
+        TranscodeError::Write(err)
+    }
+}
+
+/// Converts the next JSON value to CBOR. Converting it back gives the same
+/// tokens, numbers with a fractional part staying floats. Raw numbers keep
+/// their exact value, as floats or decimal fractions, but not always their
+/// text: `1.50` comes back as `1.5`.
+pub fn json_to_cbor(tokenizer: &mut JsonTokenizer,
+                    writer: &mut CborWriter)
+                    -> Result<(), TranscodeError> {
+    loop {
+        let token = tokenizer.next_token()?;
+        writer.json_token(token)?;
+        if token == JsonToken::Done || writer.done {
+            return Ok(());
+        }
+    }
+}
+
+// Reads the content of a decimal fraction, after its tag.
+fn read_decimal_fraction(tokenizer: &mut CborTokenizer)
+                         -> Result<DecimalFraction, TranscodeError> {
+    if tokenizer.next_token()? != CborToken::StartArray {
+        return Err(TranscodeError::NotJson);
+    }
+    let mut number = DecimalFraction {
+        negative: false,
+        mantissa: [0; MAX_BIGNUM_LEN],
+        exponent: match tokenizer.next_token()? {
+            CborToken::Integer(exponent) => exponent,
+            _ => return Err(TranscodeError::NotJson),
+        },
+    };
+    let bignum = match tokenizer.next_token()? {
+        CborToken::Integer(mantissa) => {
+            number.negative = mantissa < 0;
+            let magnitude = if mantissa < 0 {
+                (mantissa as u64).wrapping_neg()
+            } else {
+                mantissa as u64
+            };
+            for i in 0..8 {
+                number.mantissa[MAX_BIGNUM_LEN - 1 - i] = (magnitude >> (8 * i)) as u8;
+            }
+            false
+        }
+        CborToken::Tag(tag) if tag == 2 || tag == 3 => {
+            number.negative = tag == 3;
+            true
+        }
+        _ => return Err(TranscodeError::NotJson),
+    };
+    if bignum {
+        match tokenizer.next_token()? {
+            CborToken::Bytes(bytes) if bytes.len() <= MAX_BIGNUM_LEN => {
+                number.mantissa[MAX_BIGNUM_LEN - bytes.len()..].copy_from_slice(bytes);
+            }
+            _ => return Err(TranscodeError::NotJson),
+        }
+        // -1 - n for negative bignums.
+        if number.negative && !number.mul_add(1, 1) {
+            return Err(TranscodeError::NotJson);
+        }
+    }
+    if tokenizer.next_token()? != CborToken::EndArray {
+        return Err(TranscodeError::NotJson);
+    }
+    Ok(number)
+}
+
+/// Converts the next CBOR data item to JSON, following RFC 8949 section
+/// 6.1: byte strings become base64url strings without padding, or Base64 or
+/// hex strings when tagged 22 or 23. Decimal fractions, tag 4, become
+/// numbers with their exact value. Other tags are ignored, and undefined,
+/// NaN, infinite numbers and unknown simple values become `null`. The chunks
+/// of indefinite length strings are written as a single string.
+pub fn cbor_to_json<W: Write>(tokenizer: &mut CborTokenizer,
+                              writer: &mut JsonWriter<W>)
+                              -> Result<(), TranscodeError> {
+    let mut depth = 0;
+    // The encoding of the next byte string, given by its tag.
+    let mut encoding = BinaryEncoding::Base64Url;
+    // Whether the chunks of an indefinite length string are being read.
+    let mut chunked = false;
+    loop {
+        let token = tokenizer.next_token()?;
+        let key = tokenizer.is_key();
+        match token {
+            CborToken::Tag(4) if !key => {
+                let number = read_decimal_fraction(tokenizer)?;
+                // A sign, 78 digits and an exponent.
+                let mut buffer = [0u8; 128];
+                let mut text = SliceWriter::new(&mut buffer);
+                write!(text, "{}", number).map_err(JsonWriteError::from)?;
+                writer.json_number(text.as_str())?;
+            }
+            CborToken::Tag(tag) => {
+                encoding = match tag {
+                    22 => BinaryEncoding::Base64,
+                    23 => BinaryEncoding::Hex,
+                    _ => BinaryEncoding::Base64Url,
+                };
+                continue;
+            }
+            CborToken::Text(value) if chunked => writer.string_chunk(value)?,
+            CborToken::Bytes(value) if chunked => writer.binary_chunk(value)?,
+            CborToken::StartText => {
+                chunked = true;
+                if key {
+                    writer.begin_key()?;
+                } else {
+                    writer.begin_string()?;
+                }
+            }
+            CborToken::EndText => {
+                chunked = false;
+                writer.end_string()?;
+            }
+            CborToken::Text(name) if key => writer.key(name)?,
+            _ if key => return Err(TranscodeError::NotJson),
+            CborToken::StartMap => {
+                depth += 1;
+                writer.begin_object()?;
+            }
+            CborToken::EndMap => {
+                depth -= 1;
+                writer.end_object()?;
+            }
+            CborToken::StartArray => {
+                depth += 1;
+                writer.begin_array()?;
+            }
+            CborToken::EndArray => {
+                depth -= 1;
+                writer.end_array()?;
+            }
+            CborToken::Text(value) => writer.string(value)?,
+            CborToken::Bytes(value) => writer.binary(value, encoding)?,
+            CborToken::StartBytes => {
+                chunked = true;
+                writer.begin_binary(encoding)?;
+            }
+            CborToken::EndBytes => {
+                chunked = false;
+                writer.end_binary()?;
+            }
+            CborToken::Integer(value) => writer.integer(value)?,
+            CborToken::Float(value) => {
+                if value != value || value == f64::INFINITY || value == f64::NEG_INFINITY {
+                    writer.null()?;
+                } else {
+                    writer.float(value)?;
+                }
+            }
+            CborToken::Bool(value) => writer.bool(value)?,
+            CborToken::Null | CborToken::Undefined | CborToken::Simple(_) => writer.null()?,
+            CborToken::Done => return Ok(()),
+        }
+        encoding = BinaryEncoding::Base64Url;
+        if depth == 0 && !chunked {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use cbor::{cbor_to_json, json_to_cbor, CborErrorKind, CborToken, CborTokenizer, CborWriter,
+               TranscodeError};
+    use collections::String;
+    use collections::vec::Vec;
+    use core::f64;
+    use json::{JsonStr, JsonToken, JsonTokenizer};
+    use writer::{JsonWriteError, JsonWriter};
+
+    fn tokens(bytes: &[u8]) -> Vec<CborToken> {
+        let mut tokenizer = CborTokenizer::new(bytes);
+        let mut tokens = Vec::new();
+        loop {
+            match tokenizer.next_token().unwrap() {
+                CborToken::Done => return tokens,
+                token => tokens.push(token),
+            }
+        }
+    }
+
+    fn first_error(bytes: &[u8]) -> CborErrorKind {
+        let mut tokenizer = CborTokenizer::new(bytes);
+        loop {
+            match tokenizer.next_token() {
+                Ok(CborToken::Done) => panic!("The document should not parse"),
+                Ok(_) => {}
+                Err(err) => return err.kind,
+            }
+        }
+    }
+
+    // Examples from RFC 8949 appendix A.
+    #[test]
+    fn scalars() {
+        let cases: [(&[u8], CborToken); 20] =
+            [(&[0x00], CborToken::Integer(0)),
+             (&[0x17], CborToken::Integer(23)),
+             (&[0x18, 0x18], CborToken::Integer(24)),
+             (&[0x19, 0x03, 0xe8], CborToken::Integer(1000)),
+             (&[0x1b, 0x00, 0x00, 0x00, 0xe8, 0xd4, 0xa5, 0x10, 0x00],
+              CborToken::Integer(1000000000000)),
+             (&[0x20], CborToken::Integer(-1)),
+             (&[0x39, 0x03, 0xe7], CborToken::Integer(-1000)),
+             (&[0xf9, 0x3c, 0x00], CborToken::Float(1.0)),
+             (&[0xf9, 0x00, 0x01], CborToken::Float(5.960464477539063e-8)),
+             (&[0xf9, 0x7b, 0xff], CborToken::Float(65504.0)),
+             (&[0xf9, 0xc4, 0x00], CborToken::Float(-4.0)),
+             (&[0xfa, 0x47, 0xc3, 0x50, 0x00], CborToken::Float(100000.0)),
+             (&[0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a], CborToken::Float(1.1)),
+             (&[0xf9, 0x7c, 0x00], CborToken::Float(f64::INFINITY)),
+             (&[0xf4], CborToken::Bool(false)),
+             (&[0xf6], CborToken::Null),
+             (&[0xf7], CborToken::Undefined),
+             (&[0xf8, 0xff], CborToken::Simple(255)),
+             (&[0x62, 0xc3, 0xbc], CborToken::Text("ü")),
+             (&[0x44, 0x01, 0x02, 0x03, 0x04], CborToken::Bytes(&[1, 2, 3, 4]))];
+
+        for &(bytes, token) in cases.iter() {
+            assert_eq!(tokens(bytes), vec![token]);
+        }
+        assert_eq!(tokens(&[0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]),
+                   vec![CborToken::Tag(1), CborToken::Integer(1363896240)]);
+        let nan = tokens(&[0xf9, 0x7e, 0x00]);
+        assert!(match nan[0] {
+            CborToken::Float(value) => value != value,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn containers() {
+        // {"a": 1, "b": [2, 3]}
+        let definite = [0xa2, 0x61, 0x61, 0x01, 0x61, 0x62, 0x82, 0x02, 0x03];
+        // {_ "a": 1, "b": [_ 2, 3]}
+        let indefinite = [0xbf, 0x61, 0x61, 0x01, 0x61, 0x62, 0x9f, 0x02, 0x03, 0xff, 0xff];
+        let expected = vec![CborToken::StartMap,
+                            CborToken::Text("a"),
+                            CborToken::Integer(1),
+                            CborToken::Text("b"),
+                            CborToken::StartArray,
+                            CborToken::Integer(2),
+                            CborToken::Integer(3),
+                            CborToken::EndArray,
+                            CborToken::EndMap];
+        assert_eq!(tokens(&definite), expected);
+        assert_eq!(tokens(&indefinite), expected);
+        assert_eq!(tokens(&[0x80]), vec![CborToken::StartArray, CborToken::EndArray]);
+
+        let mut tokenizer = CborTokenizer::new(&definite);
+        let mut keys = Vec::new();
+        while let Ok(token) = tokenizer.next_token() {
+            if token == CborToken::Done {
+                break;
+            }
+            keys.push(tokenizer.is_key());
+        }
+        assert_eq!(keys, vec![false, true, false, true, false, false, false, false, false]);
+    }
+
+    #[test]
+    fn indefinite_strings() {
+        // {(_ "a", "b"): (_ h'0102', h'030405')}
+        let bytes = [0xa1, 0x7f, 0x61, 0x61, 0x61, 0x62, 0xff, 0x5f, 0x42, 0x01, 0x02, 0x43, 0x03,
+                     0x04, 0x05, 0xff];
+        assert_eq!(tokens(&bytes),
+                   vec![CborToken::StartMap,
+                        CborToken::StartText,
+                        CborToken::Text("a"),
+                        CborToken::Text("b"),
+                        CborToken::EndText,
+                        CborToken::StartBytes,
+                        CborToken::Bytes(&[1, 2]),
+                        CborToken::Bytes(&[3, 4, 5]),
+                        CborToken::EndBytes,
+                        CborToken::EndMap]);
+
+        let mut tokenizer = CborTokenizer::new(&bytes);
+        let mut keys = Vec::new();
+        while let Ok(token) = tokenizer.next_token() {
+            if token == CborToken::Done {
+                break;
+            }
+            keys.push(tokenizer.is_key());
+        }
+        assert_eq!(keys,
+                   vec![false, true, true, true, true, false, false, false, false, false]);
+    }
+
+    #[test]
+    fn errors() {
+        let cases: [(&[u8], CborErrorKind); 13] =
+            [(&[], CborErrorKind::UnexpectedEof),
+             (&[0x19, 0x03], CborErrorKind::UnexpectedEof),
+             (&[0x82, 0x01], CborErrorKind::UnexpectedEof),
+             (&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+              CborErrorKind::UnexpectedEof),
+             (&[0x1c], CborErrorKind::InvalidHeader),
+             (&[0x1f], CborErrorKind::InvalidHeader),
+             (&[0xff], CborErrorKind::UnexpectedBreak),
+             (&[0xbf, 0x61, 0x61, 0xff], CborErrorKind::UnexpectedBreak),
+             (&[0x62, 0xc3, 0x28], CborErrorKind::InvalidUtf8),
+             (&[0x1b, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+              CborErrorKind::NumberOverflow),
+             (&[0x7f, 0x41, 0x00, 0xff], CborErrorKind::InvalidHeader),
+             (&[0x5f, 0x5f, 0xff, 0xff], CborErrorKind::InvalidHeader),
+             (&[0x5f, 0x41], CborErrorKind::UnexpectedEof)];
+
+        for &(bytes, kind) in cases.iter() {
+            assert_eq!(first_error(bytes), kind);
+        }
+        assert_eq!(first_error(&[0x81; 40]), CborErrorKind::NestingTooDeep);
+    }
+
+    #[test]
+    fn writer() {
+        let mut buffer = [0u8; 64];
+        let mut writer = CborWriter::new(&mut buffer);
+        writer.begin_map().unwrap();
+        writer.key("a").unwrap();
+        writer.integer(-1000).unwrap();
+        writer.key("b").unwrap();
+        writer.begin_array().unwrap();
+        writer.float(1.5).unwrap();
+        writer.float(100000.0).unwrap();
+        writer.float(1.1).unwrap();
+        writer.float(5.960464477539063e-8).unwrap();
+        writer.bytes(&[1, 2]).unwrap();
+        writer.end_array().unwrap();
+        assert_eq!(writer.integer(1), Err(JsonWriteError::ExpectedKey));
+        writer.end_map().unwrap();
+        assert_eq!(writer.null(), Err(JsonWriteError::Complete));
+        assert_eq!(writer.finish().unwrap(),
+                   &[0xbf, 0x61, 0x61, 0x39, 0x03, 0xe7, 0x61, 0x62, 0x9f, 0xf9, 0x3e, 0x00,
+                     0xfa, 0x47, 0xc3, 0x50, 0x00, 0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99,
+                     0x99, 0x9a, 0xf9, 0x00, 0x01, 0x42, 0x01, 0x02, 0xff, 0xff][..]);
+
+        let mut buffer = [0u8; 2];
+        let mut writer = CborWriter::new(&mut buffer);
+        assert_eq!(writer.string("abc"), Err(JsonWriteError::Write));
+
+        // Strings in fragments.
+        let mut buffer = [0u8; 16];
+        let mut writer = CborWriter::new(&mut buffer);
+        writer.begin_array().unwrap();
+        writer.json_token(JsonToken::StartString).unwrap();
+        writer.json_token(JsonToken::StringFragment(JsonStr::new("a\\u00e9"))).unwrap();
+        assert_eq!(writer.null(), Err(JsonWriteError::UnfinishedString));
+        writer.json_token(JsonToken::StringFragment(JsonStr::new("b"))).unwrap();
+        writer.json_token(JsonToken::EndString).unwrap();
+        assert_eq!(writer.end_string(), Err(JsonWriteError::Unbalanced));
+        writer.end_array().unwrap();
+        let cbor = writer.finish().unwrap();
+        assert_eq!(cbor,
+                   &[0x9f, 0x7f, 0x63, 0x61, 0xc3, 0xa9, 0x61, 0x62, 0xff, 0xff][..]);
+        assert_eq!(tokens(cbor),
+                   vec![CborToken::StartArray,
+                        CborToken::StartText,
+                        CborToken::Text("aé"),
+                        CborToken::Text("b"),
+                        CborToken::EndText,
+                        CborToken::EndArray]);
+    }
+
+    #[test]
+    fn transcoding() {
+        let text = r#"{"name":"PM 2.5 \"fine\"\n","value":[12.5,3.0,-42,1e300],"ok":true}"#;
+        let mut buffer = [0u8; 128];
+        let mut writer = CborWriter::new(&mut buffer);
+        json_to_cbor(&mut JsonTokenizer::new(text), &mut writer).unwrap();
+        let cbor = writer.finish().unwrap();
+
+        let mut json = JsonWriter::new(String::new());
+        cbor_to_json(&mut CborTokenizer::new(cbor), &mut json).unwrap();
+        assert_eq!(json.finish().unwrap(), text);
+
+        // Values without a JSON equivalent.
+        let mut json = JsonWriter::new(String::new());
+        cbor_to_json(&mut CborTokenizer::new(&[0x83, 0xf7, 0xf9, 0x7e, 0x00, 0xc1, 0x01]),
+                     &mut json)
+            .unwrap();
+        assert_eq!(json.finish().unwrap(), "[null,null,1]");
+
+        // Byte strings, tagged 22 and 23 for Base64 and hex, and indefinite
+        // length strings.
+        let bytes = [0xa2, 0x7f, 0x61, 0x61, 0x61, 0x62, 0xff, 0x85, 0x44, 0x01, 0x02, 0x03,
+                     0xfb, 0xd6, 0x42, 0xfb, 0xff, 0xd7, 0x42, 0xfb, 0xff, 0x5f, 0x42, 0x01,
+                     0x02, 0x41, 0x03, 0xff, 0x7f, 0x62, 0x61, 0x22, 0x60, 0x61, 0x62, 0xff,
+                     0x61, 0x63, 0xf6];
+        let mut json = JsonWriter::new(String::new());
+        cbor_to_json(&mut CborTokenizer::new(&bytes), &mut json).unwrap();
+        assert_eq!(json.finish().unwrap(),
+                   r#"{"ab":["AQID-w","+/8=","fbff","AQID","a\"b"],"c":null}"#);
+        let cases: [&[u8]; 2] = [&[0xa1, 0x41, 0x00, 0x02], &[0xa1, 0x01, 0x02]];
+        for bytes in cases.iter() {
+            let mut json = JsonWriter::new(String::new());
+            assert_eq!(cbor_to_json(&mut CborTokenizer::new(bytes), &mut json),
+                       Err(TranscodeError::NotJson));
+        }
+    }
+
+    #[test]
+    fn decimal_fractions() {
+        // Raw numbers that a float holds exactly stay floats, the others keep
+        // their exact value as decimal fractions.
+        let text = "[1.50, 0.1, -2.5e-3, 1.00000000000000001, -1e400, \
+                    3.141592653589793238462643383279, -184467440737095516160.5, \
+                    -9223372036854775808.5]";
+        let mut buffer = [0u8; 128];
+        let mut writer = CborWriter::new(&mut buffer);
+        json_to_cbor(&mut JsonTokenizer::new(text).raw_numbers(true), &mut writer).unwrap();
+        let cbor = writer.finish().unwrap();
+        let mut json = JsonWriter::new(String::new());
+        cbor_to_json(&mut CborTokenizer::new(cbor), &mut json).unwrap();
+        assert_eq!(json.finish().unwrap(),
+                   "[1.5,0.1,-0.0025,100000000000000001e-17,-1e400,\
+                    3141592653589793238462643383279e-30,-1844674407370955161605e-1,\
+                    -92233720368547758085e-1]");
+
+        let mut buffer = [0u8; 16];
+        let mut writer = CborWriter::new(&mut buffer);
+        writer.json_token(JsonToken::RawNumber("-1e400")).unwrap();
+        assert_eq!(writer.finish().unwrap(), [0xc4, 0x82, 0x19, 0x01, 0x90, 0x20]);
+        let too_long = "1234567890".repeat(8) + ".5";
+        assert_eq!(CborWriter::new(&mut buffer).json_token(JsonToken::RawNumber(&too_long)),
+                   Err(JsonWriteError::InvalidNumber));
+
+        // RFC 8949 examples, 273.15 and 1.5 with a bignum mantissa.
+        let cases: [(&[u8], &str); 3] =
+            [(&[0xc4, 0x82, 0x21, 0x19, 0x6a, 0xb3], "27315e-2"),
+             (&[0xc4, 0x82, 0x20, 0xc2, 0x41, 0x0f], "15e-1"),
+             (&[0xc4, 0x82, 0x20, 0xc3, 0x41, 0x0e], "-15e-1")];
+        for &(bytes, expected) in cases.iter() {
+            let mut json = JsonWriter::new(String::new());
+            cbor_to_json(&mut CborTokenizer::new(bytes), &mut json).unwrap();
+            assert_eq!(json.finish().unwrap(), expected);
+        }
+        let cases: [&[u8]; 3] = [&[0xc4, 0x82, 0x01, 0xf9, 0x3e, 0x00],
+                                 &[0xc4, 0x81, 0x01],
+                                 &[0xc4, 0x01]];
+        for bytes in cases.iter() {
+            let mut json = JsonWriter::new(String::new());
+            assert_eq!(cbor_to_json(&mut CborTokenizer::new(bytes), &mut json),
+                       Err(TranscodeError::NotJson));
+        }
+    }
+}
@@ -0,0 +1,546 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Decimal numbers, and their exactly rounded conversion to `f32` without
+// relying on the float parsing of `core` or on libm. The conversion works on
+// big integers, which are small enough for `f32` to stay on the stack.
+
+use core::cmp::Ordering;
+use core::fmt;
+use core::mem;
+
+// Significant digits kept while parsing. Halfway points between two `f32`
+// have at most 112 significant digits, and dropped digits are remembered
+// with an extra `1` digit at the end.
+const MAX_DIGITS: usize = 120;
+
+// Exponents are clamped to this, which is way beyond the range of `f32`.
+const MAX_EXPONENT: i32 = 100000;
+
+/// A decimal number, `mantissa * 10^exponent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decimal {
+    pub mantissa: i64,
+    pub exponent: i32,
+}
+
+// The significant digits of a number, `digits * 10^exponent`.
+struct Digits {
+    negative: bool,
+    digits: [u8; MAX_DIGITS + 1],
+    count: usize,
+    exponent: i32,
+    // Whether non-zero digits were dropped.
+    truncated: bool,
+}
+
+impl Digits {
+    // Parses a number in JSON syntax.
+    fn parse(text: &str) -> Option<Digits> {
+        let bytes = text.as_bytes();
+        let mut number = Digits {
+            negative: false,
+            digits: [0; MAX_DIGITS + 1],
+            count: 0,
+            exponent: 0,
+            truncated: false,
+        };
+        let mut pos = 0;
+        if bytes.first() == Some(&b'-') {
+            number.negative = true;
+            pos += 1;
+        }
+
+        // No leading zeros, and at least one digit.
+        let start = pos;
+        while pos < bytes.len() && is_digit(bytes[pos]) {
+            if bytes[pos] != b'0' || number.count > 0 {
+                if !number.push(bytes[pos]) {
+                    number.exponent += 1;
+                }
+            }
+            pos += 1;
+        }
+        if pos == start || (pos - start > 1 && bytes[start] == b'0') {
+            return None;
+        }
+
+        if pos < bytes.len() && bytes[pos] == b'.' {
+            pos += 1;
+            let start = pos;
+            while pos < bytes.len() && is_digit(bytes[pos]) {
+                if bytes[pos] != b'0' || number.count > 0 {
+                    if number.push(bytes[pos]) {
+                        number.exponent -= 1;
+                    }
+                } else {
+                    // A leading zero.
+                    number.exponent -= 1;
+                }
+                pos += 1;
+            }
+            if pos == start {
+                return None;
+            }
+        }
+
+        if pos < bytes.len() && (bytes[pos] == b'e' || bytes[pos] == b'E') {
+            pos += 1;
+            let mut negative = false;
+            if pos < bytes.len() && (bytes[pos] == b'+' || bytes[pos] == b'-') {
+                negative = bytes[pos] == b'-';
+                pos += 1;
+            }
+            let start = pos;
+            let mut exponent: i32 = 0;
+            while pos < bytes.len() && is_digit(bytes[pos]) {
+                exponent = clamp(exponent * 10 + (bytes[pos] - b'0') as i32);
+                pos += 1;
+            }
+            if pos == start {
+                return None;
+            }
+            number.exponent = clamp(number.exponent + if negative { -exponent } else { exponent });
+        }
+
+        if pos != bytes.len() {
+            return None;
+        }
+        Some(number)
+    }
+
+    // Appends a significant digit, returning false if it was dropped.
+    fn push(&mut self, digit: u8) -> bool {
+        if self.count == MAX_DIGITS {
+            self.truncated |= digit != b'0';
+            return false;
+        }
+        self.digits[self.count] = digit - b'0';
+        self.count += 1;
+        true
+    }
+
+    fn to_f32(&self) -> f32 {
+        let mut value = Big::zero();
+        for &digit in &self.digits[..self.count] {
+            value.mul_small(10);
+            value.add_small(digit as u32);
+        }
+        let mut exponent = self.exponent;
+        if self.truncated {
+            // Makes sure that the value is not mistaken for a halfway point.
+            value.mul_small(10);
+            value.add_small(1);
+            exponent -= 1;
+        }
+        big_to_f32(value, exponent, self.negative)
+    }
+}
+
+fn is_digit(c: u8) -> bool {
+    c >= b'0' && c <= b'9'
+}
+
+fn clamp(exponent: i32) -> i32 {
+    if exponent > MAX_EXPONENT {
+        MAX_EXPONENT
+    } else if exponent < -MAX_EXPONENT {
+        -MAX_EXPONENT
+    } else {
+        exponent
+    }
+}
+
+impl Decimal {
+    /// Parses a number in JSON syntax like `-0.0125` or `1.2e-3`. Returns
+    /// `None` if the syntax is invalid, or if the number has too many
+    /// significant digits for the mantissa.
+    pub fn parse(text: &str) -> Option<Decimal> {
+        let mut number = match Digits::parse(text) {
+            Some(number) => number,
+            None => return None,
+        };
+        if number.count == 0 {
+            return Some(Decimal {
+                mantissa: 0,
+                exponent: 0,
+            });
+        }
+        // Trailing zeros can go into the exponent.
+        if number.count > 18 {
+            while number.digits[number.count - 1] == 0 {
+                number.count -= 1;
+                number.exponent += 1;
+            }
+        }
+        if number.count > 18 || number.truncated || number.exponent.abs() >= MAX_EXPONENT {
+            return None;
+        }
+        let mut mantissa = 0i64;
+        for &digit in &number.digits[..number.count] {
+            mantissa = mantissa * 10 + digit as i64;
+        }
+        Some(Decimal {
+            mantissa: if number.negative { -mantissa } else { mantissa },
+            exponent: number.exponent,
+        })
+    }
+
+    /// The closest `f32`, which may be infinite.
+    pub fn to_f32(&self) -> f32 {
+        let magnitude = if self.mantissa < 0 {
+            (self.mantissa as u64).wrapping_neg()
+        } else {
+            self.mantissa as u64
+        };
+        let mut value = Big::zero();
+        value.add_small((magnitude >> 32) as u32);
+        value.shl(32);
+        value.add_small(magnitude as u32);
+        big_to_f32(value, self.exponent, self.mantissa < 0)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.exponent >= 0 {
+            write!(f, "{}", self.mantissa)?;
+            for _ in 0..self.exponent {
+                f.write_str("0")?;
+            }
+            return Ok(());
+        }
+        if self.mantissa < 0 {
+            f.write_str("-")?;
+        }
+        let magnitude = if self.mantissa < 0 {
+            (self.mantissa as u64).wrapping_neg()
+        } else {
+            self.mantissa as u64
+        };
+        let mut digits = [0u8; 20];
+        let mut count = 0;
+        let mut rest = magnitude;
+        while rest > 0 || count == 0 {
+            digits[count] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            count += 1;
+        }
+        let decimals = (-self.exponent) as usize;
+        if decimals >= count {
+            f.write_str("0.")?;
+            for _ in count..decimals {
+                f.write_str("0")?;
+            }
+        }
+        for i in (0..count).rev() {
+            write!(f, "{}", digits[i] as char)?;
+            if i == decimals && i > 0 {
+                f.write_str(".")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a number in JSON syntax into the closest `f32`, rounding halfway
+/// cases to even. Returns `None` if the syntax is invalid or the number is
+/// out of the range of `f32`.
+pub fn parse_f32(text: &str) -> Option<f32> {
+    let value = match Digits::parse(text) {
+        Some(number) => number.to_f32(),
+        None => return None,
+    };
+    if value == ::core::f32::INFINITY || value == ::core::f32::NEG_INFINITY {
+        return None;
+    }
+    Some(value)
+}
+
+// Enough 32 bit words for the numbers used by `big_to_f32()`, which stay
+// below 2^600.
+const BIG_WORDS: usize = 24;
+
+// A fixed size unsigned big integer, little endian.
+#[derive(Clone, Copy)]
+struct Big {
+    words: [u32; BIG_WORDS],
+}
+
+impl Big {
+    fn zero() -> Self {
+        Big { words: [0; BIG_WORDS] }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    fn add_small(&mut self, value: u32) {
+        let mut carry = value as u64;
+        for word in self.words.iter_mut() {
+            if carry == 0 {
+                break;
+            }
+            let sum = *word as u64 + carry;
+            *word = sum as u32;
+            carry = sum >> 32;
+        }
+    }
+
+    fn mul_small(&mut self, value: u32) {
+        let mut carry = 0u64;
+        for word in self.words.iter_mut() {
+            let product = *word as u64 * value as u64 + carry;
+            *word = product as u32;
+            carry = product >> 32;
+        }
+    }
+
+    fn mul_pow10(&mut self, exponent: u32) {
+        let mut exponent = exponent;
+        while exponent >= 9 {
+            self.mul_small(1000000000);
+            exponent -= 9;
+        }
+        for _ in 0..exponent {
+            self.mul_small(10);
+        }
+    }
+
+    fn shl(&mut self, bits: u32) {
+        let (words, bits) = ((bits / 32) as usize, bits % 32);
+        for i in (0..BIG_WORDS).rev() {
+            let mut word = 0;
+            if i >= words {
+                word = self.words[i - words] << bits;
+                if bits > 0 && i > words {
+                    word |= self.words[i - words - 1] >> (32 - bits);
+                }
+            }
+            self.words[i] = word;
+        }
+    }
+
+    // `self -= other`, `other` being smaller.
+    fn sub(&mut self, other: &Big) {
+        let mut borrow = 0i64;
+        for i in 0..BIG_WORDS {
+            let difference = self.words[i] as i64 - other.words[i] as i64 - borrow;
+            self.words[i] = difference as u32;
+            borrow = if difference < 0 { 1 } else { 0 };
+        }
+    }
+
+    fn compare(&self, other: &Big) -> Ordering {
+        for i in (0..BIG_WORDS).rev() {
+            match self.words[i].cmp(&other.words[i]) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+        Ordering::Equal
+    }
+
+    fn bit_length(&self) -> u32 {
+        for i in (0..BIG_WORDS).rev() {
+            if self.words[i] != 0 {
+                return 32 * i as u32 + 32 - self.words[i].leading_zeros();
+            }
+        }
+        0
+    }
+
+    // Whether `self >= other << bits`.
+    fn at_least_shifted(&self, other: &Big, bits: u32) -> bool {
+        let mut shifted = *other;
+        shifted.shl(bits);
+        self.compare(&shifted) != Ordering::Less
+    }
+}
+
+// Rounds `digits * 10^exponent` to the closest `f32`.
+fn big_to_f32(digits: Big, exponent: i32, negative: bool) -> f32 {
+    let bits = if digits.is_zero() {
+        0
+    } else {
+        big_to_f32_bits(digits, exponent)
+    };
+    let sign = if negative { 0x80000000 } else { 0 };
+    unsafe { mem::transmute::<u32, f32>(sign | bits) }
+}
+
+fn big_to_f32_bits(digits: Big, exponent: i32) -> u32 {
+    const INFINITY: u32 = 0x7f800000;
+
+    // Rough bounds, using log10(2) ~ 1233 / 4096.
+    let length = digits.bit_length() as i32;
+    if ((length - 1) * 1233 >> 12) + exponent >= 39 {
+        return INFINITY;
+    }
+    if (length * 1233 >> 12) + 1 + exponent <= -46 {
+        return 0;
+    }
+
+    // We look for the binary exponent `e2` so that the value is
+    // `q * 2^e2`, with `q` a 24 bit integer unless this is a subnormal
+    // number. `q` is the quotient of `numerator / denominator`.
+    // The first guess uses log2(10) ~ 217706 / 65536.
+    let estimate = length - 1 + ((exponent as i64 * 217706) >> 16) as i32;
+    let mut e2 = if estimate - 23 < -149 { -149 } else { estimate - 23 };
+    loop {
+        let mut numerator = digits;
+        let mut denominator = Big::zero();
+        denominator.add_small(1);
+        if exponent >= 0 {
+            numerator.mul_pow10(exponent as u32);
+        } else {
+            denominator.mul_pow10((-exponent) as u32);
+        }
+        if e2 >= 0 {
+            denominator.shl(e2 as u32);
+        } else {
+            numerator.shl((-e2) as u32);
+        }
+
+        if numerator.at_least_shifted(&denominator, 24) {
+            e2 += 1;
+            continue;
+        }
+        if !numerator.at_least_shifted(&denominator, 23) && e2 > -149 {
+            e2 -= 1;
+            continue;
+        }
+
+        // Long division, the quotient being less than 2^24.
+        let mut q = 0u32;
+        for bit in (0..24).rev() {
+            if numerator.at_least_shifted(&denominator, bit) {
+                let mut shifted = denominator;
+                shifted.shl(bit);
+                numerator.sub(&shifted);
+                q |= 1 << bit;
+            }
+        }
+        // Round to nearest, ties to even, comparing twice the remainder with
+        // the denominator.
+        numerator.shl(1);
+        match numerator.compare(&denominator) {
+            Ordering::Greater => q += 1,
+            Ordering::Equal if q & 1 == 1 => q += 1,
+            _ => {}
+        }
+        if q == 1 << 24 {
+            q = 1 << 23;
+            e2 += 1;
+        }
+
+        if q < 1 << 23 {
+            // A subnormal number.
+            return q;
+        }
+        if e2 + 150 >= 255 {
+            return INFINITY;
+        }
+        return (((e2 + 150) as u32) << 23) + q - (1 << 23);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::mem;
+    use decimal::{parse_f32, Decimal};
+
+    fn bits(text: &str) -> u32 {
+        unsafe { mem::transmute::<f32, u32>(parse_f32(text).unwrap()) }
+    }
+
+    #[test]
+    fn simple_values() {
+        assert_eq!(parse_f32("0.0125"), Some(0.0125));
+        assert_eq!(parse_f32("1.2e-3"), Some(1.2e-3));
+        assert_eq!(parse_f32("-17"), Some(-17.0));
+        assert_eq!(parse_f32("0.1"), Some(0.1));
+        assert_eq!(parse_f32("3.14159"), Some(3.14159));
+        assert_eq!(parse_f32("6.02e+23"), Some(6.02e23));
+        assert_eq!(bits("0"), 0);
+        assert_eq!(bits("-0.0"), 0x80000000);
+        assert_eq!(bits("0e999999999"), 0);
+    }
+
+    #[test]
+    fn hard_cases() {
+        let cases = [// Around 1 + 2^-24, the halfway point after 1.
+                     ("1.00000005960464477539062499", 0x3f800000),
+                     ("1.000000059604644775390625", 0x3f800000),
+                     ("1.00000005960464477539062501", 0x3f800001),
+                     // Rounding to f64 first would give the halfway point.
+                     ("1.0000000596046447753906250000000001", 0x3f800001),
+                     // Ties to even for integers.
+                     ("16777217", 0x4b800000),
+                     ("16777219", 0x4b800002),
+                     // The double of 0.1, written in full.
+                     ("0.1000000000000000055511151231257827021181583404541015625", 0x3dcccccd),
+                     ("7.038531e-26", 0x15ae43fd),
+                     ("8.589973e9", 0x50000026),
+                     ("1.17549435e-38", 0x00800000),
+                     ("1.1754942e-38", 0x007fffff),
+                     ("1.401298464324817e-45", 0x00000001),
+                     ("7.006492321624085354618647916449580656401309709382578858785341419448955413\
+                      42930300743319094181060791015625e-46",
+                      0x00000000),
+                     ("7.006492321624085354618647916449580656401309709382578858785341419448955413\
+                      42930300743319094181060791015625001e-46",
+                      0x00000001),
+                     ("1e-46", 0),
+                     ("3.4028234663852886e38", 0x7f7fffff),
+                     ("3.4028235677973362e38", 0x7f7fffff),
+                     ("340282356779733661637539395458142568447.9999", 0x7f7fffff)];
+
+        for &(text, expected) in cases.iter() {
+            assert_eq!(bits(text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn invalid_and_out_of_range() {
+        let cases = ["", "-", "01", "1.", ".5", "1e", "1e+", "+1", "1 ", "0x10", "nan",
+                     "340282356779733661637539395458142568448", "1e39", "-1e100000000"];
+        for text in cases.iter() {
+            assert_eq!(parse_f32(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn decimals() {
+        let cases = [("0.0125", 125, -4),
+                     ("-1.2e-3", -12, -4),
+                     ("1.50", 150, -2),
+                     ("42", 42, 0),
+                     ("-0.0", 0, 0),
+                     ("9223372036854775e3", 9223372036854775, 3),
+                     ("100000000000000000000000", 1, 23)];
+        for &(text, mantissa, exponent) in cases.iter() {
+            assert_eq!(Decimal::parse(text),
+                       Some(Decimal {
+                           mantissa: mantissa,
+                           exponent: exponent,
+                       }));
+        }
+        assert_eq!(Decimal::parse("1234567890123456789"), None);
+        assert_eq!(Decimal::parse("1e100000"), None);
+        assert_eq!(Decimal::parse("1.5."), None);
+
+        let decimal = Decimal::parse("-0.0125").unwrap();
+        assert_eq!(decimal.to_f32(), -0.0125);
+        assert_eq!(format!("{}", decimal), "-0.0125");
+        assert_eq!(format!("{}", Decimal::parse("12.5").unwrap()), "12.5");
+        assert_eq!(format!("{}", Decimal::parse("1.5e2").unwrap()), "150");
+        assert_eq!(format!("{}", Decimal::parse("0").unwrap()), "0");
+        assert_eq!(Decimal::parse("1.2e-3").unwrap().to_f32(), 1.2e-3);
+        assert_eq!(Decimal::parse("-9223372036854775").unwrap().to_f32(),
+                   -9223372036854775.0);
+    }
+}
@@ -0,0 +1,475 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Decoding of JSON documents into Rust values. Structs get their `FromJson`
+// implementation from the `impl_from_json!` macro.
+
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+use core::{f64, fmt};
+use decimal::{parse_f32, Decimal};
+use json::{Expected, JsonError, JsonErrorKind, JsonToken, JsonTokenizer};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodeErrorKind {
+    /// The input is not valid JSON.
+    Json(JsonError),
+    /// A value doesn't have the expected type, which is given.
+    InvalidType(&'static str),
+    /// A number doesn't fit in the expected type.
+    OutOfRange,
+    MissingField,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecodeError {
+    pub kind: DecodeErrorKind,
+    /// The innermost struct field that failed to decode.
+    pub field: Option<&'static str>,
+}
+
+impl DecodeError {
+    pub fn new(kind: DecodeErrorKind) -> Self {
+        DecodeError {
+            kind: kind,
+            field: None,
+        }
+    }
+
+    pub fn invalid_type(expected: &'static str) -> Self {
+        DecodeError::new(DecodeErrorKind::InvalidType(expected))
+    }
+
+    pub fn missing_field(field: &'static str) -> Self {
+        DecodeError::new(DecodeErrorKind::MissingField).in_field(field)
+    }
+
+    /// Records the field being decoded, unless a nested one already was.
+    pub fn in_field(mut self, field: &'static str) -> Self {
+        if self.field.is_none() {
+            self.field = Some(field);
+        }
+        self
+    }
+}
+
+impl From<JsonError> for DecodeError {
+    fn from(err: JsonError) -> DecodeError {
+        DecodeError::new(DecodeErrorKind::Json(err))
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            DecodeErrorKind::Json(ref err) => write!(f, "{}", err)?,
+            DecodeErrorKind::InvalidType(expected) => write!(f, "expected {}", expected)?,
+            DecodeErrorKind::OutOfRange => f.write_str("number out of range")?,
+            DecodeErrorKind::MissingField => f.write_str("missing field")?,
+        }
+        if let Some(field) = self.field {
+            write!(f, " in field `{}`", field)?;
+        }
+        Ok(())
+    }
+}
+
+/// Types that can be decoded from a JSON value.
+pub trait FromJson: Sized {
+    /// Decodes a value whose first token, `token`, has already been read.
+    /// Objects and arrays have to be consumed up to their closing token.
+    fn from_json<'a>(token: JsonToken<'a>,
+                     tokenizer: &mut JsonTokenizer<'a>)
+                     -> Result<Self, DecodeError>;
+
+    /// The value to use when a struct field is absent, if that's allowed.
+    fn missing() -> Option<Self> {
+        None
+    }
+}
+
+/// Decodes a whole document, which has to be valid RFC 8259 JSON with
+/// nothing after the root value.
+pub fn decode<T: FromJson>(text: &str) -> Result<T, DecodeError> {
+    decode_with(&mut JsonTokenizer::new(text).strict(true))
+}
+
+/// Decodes the document read by `tokenizer`, up to its `Done` token. Content
+/// after the root value is only an error in strict mode.
+pub fn decode_with<'a, T: FromJson>(tokenizer: &mut JsonTokenizer<'a>) -> Result<T, DecodeError> {
+    let token = tokenizer.next_token()?;
+    let value = T::from_json(token, tokenizer)?;
+    if tokenizer.next_token()? != JsonToken::Done {
+        return Err(DecodeError::from(JsonError::at(tokenizer.input(),
+                                                   tokenizer.token_offset(),
+                                                   JsonErrorKind::TrailingContent,
+                                                   Some(Expected::End))));
+    }
+    Ok(value)
+}
+
+impl FromJson for bool {
+    fn from_json<'a>(token: JsonToken<'a>, _: &mut JsonTokenizer<'a>) -> Result<Self, DecodeError> {
+        match token {
+            JsonToken::True => Ok(true),
+            JsonToken::False => Ok(false),
+            _ => Err(DecodeError::invalid_type("a boolean")),
+        }
+    }
+}
+
+impl FromJson for i64 {
+    fn from_json<'a>(token: JsonToken<'a>, _: &mut JsonTokenizer<'a>) -> Result<Self, DecodeError> {
+        match token {
+            JsonToken::Integer(value) => Ok(value),
+            _ => Err(DecodeError::invalid_type("an integer")),
+        }
+    }
+}
+
+macro_rules! from_json_integer {
+    ($($t:ident),*) => ($(
+        impl FromJson for $t {
+            fn from_json<'a>(token: JsonToken<'a>,
+                             tokenizer: &mut JsonTokenizer<'a>)
+                             -> Result<Self, DecodeError> {
+                let value = i64::from_json(token, tokenizer)?;
+                if value < ::core::$t::MIN as i64 || value > ::core::$t::MAX as i64 {
+                    return Err(DecodeError::new(DecodeErrorKind::OutOfRange));
+                }
+                Ok(value as $t)
+            }
+        }
+    )*)
+}
+
+from_json_integer!(i8, i16, i32, u8, u16, u32);
+
+impl FromJson for u64 {
+    fn from_json<'a>(token: JsonToken<'a>,
+                     tokenizer: &mut JsonTokenizer<'a>)
+                     -> Result<Self, DecodeError> {
+        let value = i64::from_json(token, tokenizer)?;
+        if value < 0 {
+            return Err(DecodeError::new(DecodeErrorKind::OutOfRange));
+        }
+        Ok(value as u64)
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json<'a>(token: JsonToken<'a>, _: &mut JsonTokenizer<'a>) -> Result<Self, DecodeError> {
+        match token {
+            JsonToken::Number(value) => Ok(value),
+            JsonToken::Integer(value) => Ok(value as f64),
+            JsonToken::RawNumber(text) => {
+                match text.parse::<f64>() {
+                    Ok(value) if value != f64::INFINITY && value != f64::NEG_INFINITY => Ok(value),
+                    _ => Err(DecodeError::new(DecodeErrorKind::OutOfRange)),
+                }
+            }
+            _ => Err(DecodeError::invalid_type("a number")),
+        }
+    }
+}
+
+/// Raw numbers are rounded once, without using the `f64` parser.
+impl FromJson for f32 {
+    fn from_json<'a>(token: JsonToken<'a>, _: &mut JsonTokenizer<'a>) -> Result<Self, DecodeError> {
+        match token {
+            JsonToken::Number(value) => Ok(value as f32),
+            JsonToken::Integer(value) => Ok(value as f32),
+            JsonToken::RawNumber(text) => {
+                parse_f32(text).ok_or(DecodeError::new(DecodeErrorKind::OutOfRange))
+            }
+            _ => Err(DecodeError::invalid_type("a number")),
+        }
+    }
+}
+
+/// Numbers have to be read with `JsonTokenizer::raw_numbers()`, as their
+/// `f64` value isn't exact.
+impl FromJson for Decimal {
+    fn from_json<'a>(token: JsonToken<'a>, _: &mut JsonTokenizer<'a>) -> Result<Self, DecodeError> {
+        match token {
+            JsonToken::Integer(value) => {
+                Ok(Decimal {
+                    mantissa: value,
+                    exponent: 0,
+                })
+            }
+            JsonToken::RawNumber(text) => {
+                Decimal::parse(text).ok_or(DecodeError::new(DecodeErrorKind::OutOfRange))
+            }
+            _ => Err(DecodeError::invalid_type("a raw number")),
+        }
+    }
+}
+
+impl FromJson for String {
+    fn from_json<'a>(token: JsonToken<'a>, _: &mut JsonTokenizer<'a>) -> Result<Self, DecodeError> {
+        match token {
+            JsonToken::String(value) => Ok(value.to_string()),
+            _ => Err(DecodeError::invalid_type("a string")),
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json<'a>(token: JsonToken<'a>,
+                     tokenizer: &mut JsonTokenizer<'a>)
+                     -> Result<Self, DecodeError> {
+        match token {
+            JsonToken::Null => Ok(None),
+            _ => T::from_json(token, tokenizer).map(Some),
+        }
+    }
+
+    fn missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json<'a>(token: JsonToken<'a>,
+                     tokenizer: &mut JsonTokenizer<'a>)
+                     -> Result<Self, DecodeError> {
+        match token {
+            JsonToken::StartArray => {}
+            _ => return Err(DecodeError::invalid_type("an array")),
+        }
+        let mut values = Vec::new();
+        loop {
+            match tokenizer.next_token()? {
+                JsonToken::EndArray => return Ok(values),
+                token => values.push(T::from_json(token, tokenizer)?),
+            }
+        }
+    }
+}
+
+/// Implements `FromJson` for a struct, mapping each field to an object key.
+/// Unknown keys are skipped, and `Option` fields may be absent.
+///
+/// ```ignore
+/// struct TimeResponse {
+///     time: i64,
+///     iso_date: Option<String>,
+/// }
+///
+/// impl_from_json!(TimeResponse {
+///     time: "time",
+///     iso_date: "isoDate",
+/// });
+/// ```
+#[macro_export]
+macro_rules! impl_from_json {
+    ($name:ident { $($field:ident : $key:expr),* $(,)* }) => (
+        impl $crate::FromJson for $name {
+            fn from_json<'a>(token: $crate::JsonToken<'a>,
+                             tokenizer: &mut $crate::JsonTokenizer<'a>)
+                             -> Result<Self, $crate::DecodeError> {
+                match token {
+                    $crate::JsonToken::StartObject => {}
+                    _ => return Err($crate::DecodeError::invalid_type("an object")),
+                }
+                $(let mut $field = None;)*
+                loop {
+                    let name = match tokenizer.next_token()? {
+                        $crate::JsonToken::PropertyName(name) => name,
+                        _ => break,
+                    };
+                    let value = tokenizer.next_token()?;
+                    $(
+                        if name == $key {
+                            $field = Some($crate::FromJson::from_json(value, tokenizer)
+                                .map_err(|err| err.in_field($key))?);
+                            continue;
+                        }
+                    )*
+                    $crate::skip_value(value, tokenizer)?;
+                }
+                Ok($name {
+                    $(
+                        $field: match $field {
+                            Some(value) => value,
+                            None => {
+                                match $crate::FromJson::missing() {
+                                    Some(value) => value,
+                                    None => return Err($crate::DecodeError::missing_field($key)),
+                                }
+                            }
+                        },
+                    )*
+                })
+            }
+        }
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use collections::String;
+    use collections::string::ToString;
+    use collections::vec::Vec;
+    use decimal::Decimal;
+    use decode::{decode, decode_with, DecodeError, DecodeErrorKind};
+    use json::{JsonErrorKind, JsonTokenizer};
+
+    #[derive(Debug, PartialEq)]
+    struct TimeResponse {
+        time: i64,
+        iso_date: Option<String>,
+    }
+
+    impl_from_json!(TimeResponse {
+        time: "time",
+        iso_date: "isoDate",
+    });
+
+    #[derive(Debug, PartialEq)]
+    struct Sampling {
+        interval: u32,
+        sensors: Vec<String>,
+    }
+
+    impl_from_json!(Sampling {
+        interval: "interval",
+        sensors: "sensors",
+    });
+
+    #[derive(Debug, PartialEq)]
+    struct Config {
+        name: String,
+        enabled: bool,
+        threshold: f32,
+        sampling: Sampling,
+        offsets: Vec<Vec<i8>>,
+        comment: Option<String>,
+    }
+
+    impl_from_json!(Config {
+        name: "name",
+        enabled: "enabled",
+        threshold: "threshold",
+        sampling: "sampling",
+        offsets: "offsets",
+        comment: "comment",
+    });
+
+    #[test]
+    fn time_response() {
+        let text = r#"{"time":1480556487,"isoDate":"2016-12-01T01:41:27Z"}"#;
+        assert_eq!(decode::<TimeResponse>(text).unwrap(),
+                   TimeResponse {
+                       time: 1480556487,
+                       iso_date: Some(String::from("2016-12-01T01:41:27Z")),
+                   });
+
+        let text = r#"{"time":1480556487}"#;
+        assert_eq!(decode::<TimeResponse>(text).unwrap(),
+                   TimeResponse {
+                       time: 1480556487,
+                       iso_date: None,
+                   });
+    }
+
+    #[test]
+    fn nested_struct() {
+        let text = r#"{
+  "name": "station é",
+  "unknown": {"deep": [1, {"deeper": []}]},
+  "enabled": true,
+  "threshold": 0.5,
+  "sampling": {"interval": 60, "sensors": ["pm25", "pm10"], "extra": null},
+  "offsets": [[1, -2], []],
+  "comment": null
+}"#;
+        assert_eq!(decode::<Config>(text).unwrap(),
+                   Config {
+                       name: String::from("station é"),
+                       enabled: true,
+                       threshold: 0.5,
+                       sampling: Sampling {
+                           interval: 60,
+                           sensors: vec![String::from("pm25"), String::from("pm10")],
+                       },
+                       offsets: vec![vec![1, -2], vec![]],
+                       comment: None,
+                   });
+    }
+
+    #[test]
+    fn raw_numbers() {
+        // Just above halfway between two `f32`, which is lost by rounding to
+        // `f64` first.
+        let text = "1.00000005960464477539062500000000001";
+        assert_eq!(decode::<f32>(text).unwrap(), 1.0);
+        let mut tokenizer = JsonTokenizer::new(text).raw_numbers(true);
+        assert_eq!(decode_with::<f32>(&mut tokenizer).unwrap(), 1.0000001);
+
+        let mut tokenizer = JsonTokenizer::new("[0.0125, -3, 1.2e-3]").raw_numbers(true);
+        assert_eq!(decode_with::<Vec<Decimal>>(&mut tokenizer).unwrap(),
+                   vec![Decimal {
+                            mantissa: 125,
+                            exponent: -4,
+                        },
+                        Decimal {
+                            mantissa: -3,
+                            exponent: 0,
+                        },
+                        Decimal {
+                            mantissa: 12,
+                            exponent: -4,
+                        }]);
+        assert_eq!(decode::<Decimal>("0.5").unwrap_err().kind,
+                   DecodeErrorKind::InvalidType("a raw number"));
+        let mut tokenizer = JsonTokenizer::new("1e39").raw_numbers(true);
+        assert_eq!(decode_with::<f32>(&mut tokenizer).unwrap_err().kind,
+                   DecodeErrorKind::OutOfRange);
+    }
+
+    #[test]
+    fn errors() {
+        let error = decode::<TimeResponse>(r#"{"isoDate":"now"}"#).unwrap_err();
+        assert_eq!((error.kind, error.field), (DecodeErrorKind::MissingField, Some("time")));
+
+        let error = decode::<TimeResponse>(r#"{"time":"now"}"#).unwrap_err();
+        assert_eq!((error.kind, error.field),
+                   (DecodeErrorKind::InvalidType("an integer"), Some("time")));
+
+        let text = r#"{"name":"a","enabled":true,"threshold":1,"offsets":[],
+                       "sampling":{"interval":-1,"sensors":[]}}"#;
+        let error = decode::<Config>(text).unwrap_err();
+        assert_eq!((error.kind, error.field), (DecodeErrorKind::OutOfRange, Some("interval")));
+        assert_eq!(error.to_string(), "number out of range in field `interval`");
+
+        let text = r#"{"name":"a","offsets":[[1000]]}"#;
+        let error = decode::<Config>(text).unwrap_err();
+        assert_eq!((error.kind, error.field), (DecodeErrorKind::OutOfRange, Some("offsets")));
+
+        let error = decode::<TimeResponse>(r#"[1480556487]"#).unwrap_err();
+        assert_eq!((error.kind, error.field), (DecodeErrorKind::InvalidType("an object"), None));
+
+        let error = decode::<TimeResponse>(r#"{"time":1480556487"#).unwrap_err();
+        match error.kind {
+            DecodeErrorKind::Json(err) => assert_eq!(err.kind, JsonErrorKind::UnexpecteEof),
+            _ => panic!("Unexpected error {:?}", error),
+        }
+
+        // Nothing may follow the document.
+        let json_error = |error: DecodeError| match error.kind {
+            DecodeErrorKind::Json(err) => (err.kind, err.offset),
+            _ => panic!("Unexpected error {:?}", error),
+        };
+        assert_eq!(json_error(decode::<i64>("1 2 garbage").unwrap_err()),
+                   (JsonErrorKind::TrailingContent, 2));
+        assert_eq!(json_error(decode::<TimeResponse>(r#"{"time":1} {}"#).unwrap_err()),
+                   (JsonErrorKind::TrailingContent, 11));
+        assert_eq!(json_error(decode::<Vec<i64>>("[1]]").unwrap_err()),
+                   (JsonErrorKind::TrailingContent, 3));
+        assert_eq!(decode::<i64>(" 1 \n").unwrap(), 1);
+    }
+}
@@ -0,0 +1,385 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A read-only document tree for random access, built into a caller provided
+// pool of nodes so that no heap is needed.
+
+use core::fmt::Write;
+use json::{Expected, JsonError, JsonErrorKind, JsonStr, JsonToken, JsonTokenizer};
+use writer::{JsonWriteError, JsonWriter};
+
+/// Storage for one value of a document tree. A pool is declared as
+/// `[JsonNode::new(); N]`, and a document uses one node per value.
+#[derive(Clone, Copy, Debug)]
+pub struct JsonNode<'a> {
+    token: JsonToken<'a>,
+    // The property name, for the members of an object.
+    key: Option<JsonStr<'a>>,
+    // Number of elements or members, for arrays and objects.
+    len: usize,
+    // Number of nodes used by this value, including itself. While a
+    // container is being built, this is the index of its parent plus one.
+    size: usize,
+}
+
+impl<'a> JsonNode<'a> {
+    pub fn new() -> Self {
+        JsonNode {
+            token: JsonToken::Null,
+            key: None,
+            len: 0,
+            size: 1,
+        }
+    }
+}
+
+/// A value of a document tree. Values are cheap to copy and only borrow the
+/// nodes and the input, so a document parsed once can be queried by several
+/// tasks.
+#[derive(Clone, Copy, Debug)]
+pub struct JsonValue<'n, 'a: 'n> {
+    // The nodes of this value, in document order.
+    nodes: &'n [JsonNode<'a>],
+}
+
+impl<'n, 'a> JsonValue<'n, 'a> {
+    /// Reads the next value from `tokenizer` into `nodes`. Fails with an
+    /// `OutOfNodes` error if the value needs more nodes than available.
+    pub fn parse(tokenizer: &mut JsonTokenizer<'a>,
+                 nodes: &'n mut [JsonNode<'a>])
+                 -> Result<JsonValue<'n, 'a>, JsonError> {
+        let mut used = 0;
+        // Index of the innermost open container plus one, or 0.
+        let mut open = 0;
+        let mut key = None;
+        loop {
+            let token = tokenizer.next_token()?;
+            let offset = tokenizer.token_offset();
+            match token {
+                JsonToken::PropertyName(name) => {
+                    key = Some(name);
+                    continue;
+                }
+                JsonToken::EndObject | JsonToken::EndArray if open > 0 => {
+                    let index = open - 1;
+                    open = nodes[index].size;
+                    nodes[index].size = used - index;
+                }
+                JsonToken::EndObject | JsonToken::EndArray | JsonToken::Done => {
+                    // There was no value to read.
+                    let kind = if token == JsonToken::Done {
+                        JsonErrorKind::UnexpecteEof
+                    } else {
+                        JsonErrorKind::UnexpectedCharacter
+                    };
+                    return Err(JsonError::at(tokenizer.input(),
+                                             offset,
+                                             kind,
+                                             Some(Expected::Value)));
+                }
+                _ => {
+                    if used == nodes.len() {
+                        return Err(JsonError::at(tokenizer.input(),
+                                                 offset,
+                                                 JsonErrorKind::OutOfNodes,
+                                                 Some(Expected::Value)));
+                    }
+                    if open > 0 {
+                        nodes[open - 1].len += 1;
+                    }
+                    nodes[used] = JsonNode {
+                        token: token,
+                        key: key.take(),
+                        len: 0,
+                        size: 1,
+                    };
+                    if token == JsonToken::StartObject || token == JsonToken::StartArray {
+                        nodes[used].size = open;
+                        open = used + 1;
+                    }
+                    used += 1;
+                }
+            }
+            if open == 0 {
+                break;
+            }
+        }
+        let nodes: &'n [JsonNode<'a>] = nodes;
+        Ok(JsonValue { nodes: &nodes[..used] })
+    }
+
+    /// The token this value starts with: `StartObject`, `StartArray` or the
+    /// scalar itself.
+    pub fn token(&self) -> JsonToken<'a> {
+        self.nodes[0].token
+    }
+
+    pub fn is_object(&self) -> bool {
+        self.token() == JsonToken::StartObject
+    }
+
+    pub fn is_array(&self) -> bool {
+        self.token() == JsonToken::StartArray
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.token() == JsonToken::Null
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.token() {
+            JsonToken::True => Some(true),
+            JsonToken::False => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.token() {
+            JsonToken::Integer(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns integers too, converted to floating point.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.token() {
+            JsonToken::Integer(value) => Some(value as f64),
+            JsonToken::Number(value) => Some(value),
+            JsonToken::RawNumber(text) => text.parse::<f64>().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<JsonStr<'a>> {
+        match self.token() {
+            JsonToken::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Number of elements of an array or members of an object, 0 for other
+    /// values.
+    pub fn len(&self) -> usize {
+        self.nodes[0].len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of nodes used by this value and everything it contains.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The element at `index` of an array.
+    pub fn at(&self, index: usize) -> Option<JsonValue<'n, 'a>> {
+        if !self.is_array() {
+            return None;
+        }
+        self.iter().nth(index)
+    }
+
+    /// The value of the first member of an object named `key`.
+    pub fn get(&self, key: &str) -> Option<JsonValue<'n, 'a>> {
+        self.members().find(|&(name, _)| name == key).map(|(_, value)| value)
+    }
+
+    /// Iterates over the elements of an array, or the values of the members
+    /// of an object.
+    pub fn iter(&self) -> JsonElements<'n, 'a> {
+        JsonElements {
+            nodes: &self.nodes[1..],
+            remaining: self.len(),
+        }
+    }
+
+    /// Iterates over the names and values of the members of an object.
+    pub fn members(&self) -> JsonMembers<'n, 'a> {
+        let mut elements = self.iter();
+        if !self.is_object() {
+            elements.remaining = 0;
+        }
+        JsonMembers { elements: elements }
+    }
+
+    /// Writes this value and everything it contains.
+    pub fn write<W: Write>(&self, writer: &mut JsonWriter<W>) -> Result<(), JsonWriteError> {
+        match self.token() {
+            JsonToken::StartObject => {
+                writer.begin_object()?;
+                for (key, value) in self.members() {
+                    writer.json_key(key)?;
+                    value.write(writer)?;
+                }
+                writer.end_object()
+            }
+            JsonToken::StartArray => {
+                writer.begin_array()?;
+                for value in self.iter() {
+                    value.write(writer)?;
+                }
+                writer.end_array()
+            }
+            JsonToken::String(value) => writer.json_string(value),
+            JsonToken::Integer(value) => writer.integer(value),
+            JsonToken::Number(value) => writer.float(value),
+            JsonToken::RawNumber(text) => writer.json_number(text),
+            JsonToken::True => writer.bool(true),
+            JsonToken::False => writer.bool(false),
+            _ => writer.null(),
+        }
+    }
+}
+
+/// Values are equal if they have the same type and contents, whatever the
+/// order of the members of objects. Integers and numbers are never equal.
+impl<'n, 'a, 'm, 'b> PartialEq<JsonValue<'m, 'b>> for JsonValue<'n, 'a> {
+    fn eq(&self, other: &JsonValue<'m, 'b>) -> bool {
+        match (self.token(), other.token()) {
+            (JsonToken::StartObject, JsonToken::StartObject) => {
+                self.len() == other.len() &&
+                self.members().all(|(key, value)| {
+                    other.members().any(|(other_key, other_value)| {
+                        key == other_key && value == other_value
+                    })
+                })
+            }
+            (JsonToken::StartArray, JsonToken::StartArray) => {
+                self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+            }
+            (a, b) => a == b,
+        }
+    }
+}
+
+pub struct JsonElements<'n, 'a: 'n> {
+    nodes: &'n [JsonNode<'a>],
+    remaining: usize,
+}
+
+impl<'n, 'a> Iterator for JsonElements<'n, 'a> {
+    type Item = JsonValue<'n, 'a>;
+
+    fn next(&mut self) -> Option<JsonValue<'n, 'a>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let (value, rest) = self.nodes.split_at(self.nodes[0].size);
+        self.nodes = rest;
+        Some(JsonValue { nodes: value })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+pub struct JsonMembers<'n, 'a: 'n> {
+    elements: JsonElements<'n, 'a>,
+}
+
+impl<'n, 'a> Iterator for JsonMembers<'n, 'a> {
+    type Item = (JsonStr<'a>, JsonValue<'n, 'a>);
+
+    fn next(&mut self) -> Option<(JsonStr<'a>, JsonValue<'n, 'a>)> {
+        self.elements.next().map(|value| (value.nodes[0].key.unwrap(), value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.elements.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use collections::vec::Vec;
+    use dom::{JsonNode, JsonValue};
+    use json::{JsonErrorKind, JsonStr, JsonToken, JsonTokenizer};
+
+    static CONFIG: &'static str = r#"{
+  "wifi": {"ssid": "sensorweb", "channels": [1, 6, 11]},
+  "interval": 60,
+  "scale": 0.5,
+  "debug": false,
+  "proxy": null,
+  "sensors": [{"name": "pm25"}, {"name": "température"}, []]
+}"#;
+
+    #[test]
+    fn lookups() {
+        let mut nodes = [JsonNode::new(); 32];
+        let config = JsonValue::parse(&mut JsonTokenizer::new(CONFIG), &mut nodes).unwrap();
+
+        assert!(config.is_object());
+        assert_eq!(config.len(), 6);
+        assert_eq!(config.node_count(), 17);
+        assert_eq!(config.get("interval").and_then(|v| v.as_i64()), Some(60));
+        assert_eq!(config.get("interval").and_then(|v| v.as_f64()), Some(60.0));
+        assert_eq!(config.get("scale").and_then(|v| v.as_f64()), Some(0.5));
+        assert_eq!(config.get("debug").and_then(|v| v.as_bool()), Some(false));
+        assert!(config.get("proxy").unwrap().is_null());
+        assert!(config.get("missing").is_none());
+
+        let wifi = config.get("wifi").unwrap();
+        assert_eq!(wifi.get("ssid").and_then(|v| v.as_str()),
+                   Some(JsonStr::new("sensorweb")));
+        let channels = wifi.get("channels").unwrap();
+        assert_eq!(channels.at(2).and_then(|v| v.as_i64()), Some(11));
+        assert!(channels.at(3).is_none());
+        assert!(channels.get("0").is_none());
+        assert!(wifi.at(0).is_none());
+
+        let sensors = config.get("sensors").unwrap();
+        assert_eq!(sensors.at(1).and_then(|v| v.get("name")).and_then(|v| v.as_str()),
+                   Some(JsonStr::new("température")));
+        assert!(sensors.at(2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn iteration() {
+        let mut nodes = [JsonNode::new(); 32];
+        let config = JsonValue::parse(&mut JsonTokenizer::new(CONFIG), &mut nodes).unwrap();
+
+        let names = config.members().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names,
+                   ["wifi", "interval", "scale", "debug", "proxy", "sensors"]
+                       .iter()
+                       .map(|&name| JsonStr::new(name))
+                       .collect::<Vec<_>>());
+
+        let channels = config.get("wifi").and_then(|v| v.get("channels")).unwrap();
+        assert_eq!(channels.iter().map(|v| v.token()).collect::<Vec<_>>(),
+                   vec![JsonToken::Integer(1), JsonToken::Integer(6), JsonToken::Integer(11)]);
+        assert_eq!(channels.members().count(), 0);
+        assert_eq!(config.get("sensors").unwrap().iter().count(), 3);
+        assert_eq!(config.get("scale").unwrap().iter().count(), 0);
+    }
+
+    #[test]
+    fn scalars_and_errors() {
+        let mut nodes = [JsonNode::new(); 4];
+        let value = JsonValue::parse(&mut JsonTokenizer::new(" 42 "), &mut nodes).unwrap();
+        assert_eq!((value.as_i64(), value.node_count(), value.len()), (Some(42), 1, 0));
+
+        let mut nodes = [JsonNode::new(); 4];
+        let error = JsonValue::parse(&mut JsonTokenizer::new("[1, [2, 3]]"), &mut nodes)
+            .unwrap_err();
+        assert_eq!((error.kind, error.offset), (JsonErrorKind::OutOfNodes, 8));
+
+        let cases = [("", JsonErrorKind::UnexpecteEof),
+                     ("[1, 2", JsonErrorKind::UnexpecteEof),
+                     ("{\"a\": x}", JsonErrorKind::UnexpectedCharacter)];
+        for &(text, ref kind) in cases.iter() {
+            let mut nodes = [JsonNode::new(); 4];
+            assert_eq!(JsonValue::parse(&mut JsonTokenizer::new(text), &mut nodes)
+                           .unwrap_err()
+                           .kind,
+                       *kind);
+        }
+    }
+}
@@ -2,29 +2,335 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this file,
 // You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use collections::string::String;
+use collections::string::{String, ToString};
+use core::char;
+use core::cmp;
 use core::convert::From;
+use core::f64;
+use core::fmt::{self, Write};
 use core::str;
+use core::usize;
+use stack::NestingStack;
+pub use stack::MAX_DEPTH;
 
-#[derive(Debug, PartialEq)]
-pub enum JsonToken {
+/// A token borrowing its text from the tokenizer input. Nothing is allocated
+/// while tokenizing; use `into_owned()` to keep a token around.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JsonToken<'a> {
+    StartObject,
+    EndObject,
+    StartArray,
+    EndArray,
+    PropertyName(JsonStr<'a>),
+    String(JsonStr<'a>),
+    Integer(i64),
+    Number(f64),
+    /// The text of a number, see `JsonTokenizer::raw_numbers()`.
+    RawNumber(&'a str),
+    /// A string value too long for the buffer of a `JsonStreamTokenizer`,
+    /// whose parts follow as `StringFragment` tokens until `EndString`. See
+    /// `JsonStreamTokenizer::string_fragments()`.
+    StartString,
+    StringFragment(JsonStr<'a>),
+    EndString,
+    Null,
+    True,
+    False,
+    Done,
+}
+
+/// Heap allocated version of `JsonToken`, with the strings decoded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OwnedJsonToken {
     StartObject,
     EndObject,
     StartArray,
     EndArray,
     PropertyName(String),
-    Literal(String),
+    String(String),
+    Integer(i64),
+    Number(f64),
+    RawNumber(String),
+    StartString,
+    StringFragment(String),
+    EndString,
     Null,
     True,
     False,
     Done,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum JsonError {
+impl<'a> JsonToken<'a> {
+    pub fn into_owned(self) -> OwnedJsonToken {
+        match self {
+            JsonToken::StartObject => OwnedJsonToken::StartObject,
+            JsonToken::EndObject => OwnedJsonToken::EndObject,
+            JsonToken::StartArray => OwnedJsonToken::StartArray,
+            JsonToken::EndArray => OwnedJsonToken::EndArray,
+            JsonToken::PropertyName(name) => OwnedJsonToken::PropertyName(name.to_string()),
+            JsonToken::String(value) => OwnedJsonToken::String(value.to_string()),
+            JsonToken::Integer(value) => OwnedJsonToken::Integer(value),
+            JsonToken::Number(value) => OwnedJsonToken::Number(value),
+            JsonToken::RawNumber(text) => OwnedJsonToken::RawNumber(text.to_string()),
+            JsonToken::StartString => OwnedJsonToken::StartString,
+            JsonToken::StringFragment(value) => OwnedJsonToken::StringFragment(value.to_string()),
+            JsonToken::EndString => OwnedJsonToken::EndString,
+            JsonToken::Null => OwnedJsonToken::Null,
+            JsonToken::True => OwnedJsonToken::True,
+            JsonToken::False => OwnedJsonToken::False,
+            JsonToken::Done => OwnedJsonToken::Done,
+        }
+    }
+}
+
+/// The text of a JSON string, as it appears between the quotes in the input.
+/// Escape sequences have been validated by the tokenizer but are only decoded
+/// when the characters are actually needed.
+#[derive(Clone, Copy, Debug)]
+pub struct JsonStr<'a> {
+    raw: &'a str,
+    escaped: bool,
+}
+
+impl<'a> JsonStr<'a> {
+    /// Wraps raw JSON string content, which must not contain invalid escapes.
+    pub fn new(raw: &'a str) -> Self {
+        JsonStr {
+            raw: raw,
+            escaped: raw.as_bytes().contains(&b'\\'),
+        }
+    }
+
+    /// The undecoded text, escape sequences included.
+    pub fn raw(&self) -> &'a str {
+        self.raw
+    }
+
+    pub fn has_escapes(&self) -> bool {
+        self.escaped
+    }
+
+    /// Returns the text without copying it if there is nothing to decode.
+    pub fn as_str(&self) -> Option<&'a str> {
+        if self.escaped { None } else { Some(self.raw) }
+    }
+
+    /// Iterates over the decoded characters.
+    pub fn chars(&self) -> JsonStrChars<'a> {
+        JsonStrChars { raw: self.raw.as_bytes(), pos: 0 }
+    }
+
+    /// Whether the decoded text is `text`, decoding escapes on the fly
+    /// instead of copying the string. Same as `==`.
+    pub fn matches(&self, text: &str) -> bool {
+        if !self.escaped {
+            return self.raw == text;
+        }
+        // Escapes are never shorter than what they decode to.
+        text.len() <= self.raw.len() && self.chars().eq(text.chars())
+    }
+
+    /// Index of the first of `keys` that matches, to dispatch on property
+    /// names with a table instead of a chain of comparisons.
+    pub fn find_in(&self, keys: &[&str]) -> Option<usize> {
+        keys.iter().position(|key| self.matches(key))
+    }
+}
+
+impl<'a> fmt::Display for JsonStr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.escaped {
+            return f.write_str(self.raw);
+        }
+        for c in self.chars() {
+            f.write_char(c)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'b> PartialEq<JsonStr<'b>> for JsonStr<'a> {
+    fn eq(&self, other: &JsonStr<'b>) -> bool {
+        if !self.escaped && !other.escaped {
+            return self.raw == other.raw;
+        }
+        self.chars().eq(other.chars())
+    }
+}
+
+impl<'a, 'b> PartialEq<&'b str> for JsonStr<'a> {
+    fn eq(&self, other: &&'b str) -> bool {
+        self.matches(other)
+    }
+}
+
+/// Decoding iterator returned by `JsonStr::chars()`.
+pub struct JsonStrChars<'a> {
+    raw: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for JsonStrChars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.pos >= self.raw.len() {
+            return None;
+        }
+        if self.raw[self.pos] == b'\\' {
+            // The tokenizer already validated the escape sequences.
+            let (c, used) = decode_escape(&self.raw[self.pos + 1..])
+                .unwrap_or((char::REPLACEMENT_CHARACTER, self.raw.len() - self.pos - 1));
+            self.pos += used + 1;
+            return Some(c);
+        }
+        // Find the end of the unescaped run, which is valid UTF-8.
+        let end = self.raw[self.pos..]
+            .iter()
+            .position(|&c| c == b'\\')
+            .map_or(self.raw.len(), |offset| self.pos + offset);
+        let run = unsafe { str::from_utf8_unchecked(&self.raw[self.pos..end]) };
+        let c = run.chars().next().unwrap();
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JsonErrorKind {
     UnexpectedCharacter,
     UnexpecteEof,
     InvalidString,
+    InvalidEscape,
+    InvalidSurrogate,
+    InvalidNumber,
+    NumberOverflow,
+    NestingTooDeep,
+    TokenTooLong,
+    ControlCharacter,
+    TrailingComma,
+    TrailingContent,
+    StringTooLong,
+    NumberTooLong,
+    TooManyTokens,
+    OutOfNodes,
+    DuplicateKey,
+    /// An object has more members than `DuplicateKeys` can remember.
+    TooManyKeys,
+}
+
+impl fmt::Display for JsonErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            JsonErrorKind::UnexpectedCharacter => "unexpected character",
+            JsonErrorKind::UnexpecteEof => "unexpected end of input",
+            JsonErrorKind::InvalidString => "invalid UTF-8 in string",
+            JsonErrorKind::InvalidEscape => "invalid escape sequence",
+            JsonErrorKind::InvalidSurrogate => "invalid surrogate pair",
+            JsonErrorKind::InvalidNumber => "invalid number",
+            JsonErrorKind::NumberOverflow => "number out of range",
+            JsonErrorKind::NestingTooDeep => "too many nested objects and arrays",
+            JsonErrorKind::TokenTooLong => "token too long for the buffer",
+            JsonErrorKind::ControlCharacter => "unescaped control character in string",
+            JsonErrorKind::TrailingComma => "trailing comma",
+            JsonErrorKind::TrailingContent => "trailing content after the document",
+            JsonErrorKind::StringTooLong => "string longer than the limit",
+            JsonErrorKind::NumberTooLong => "number longer than the limit",
+            JsonErrorKind::TooManyTokens => "more tokens than the limit",
+            JsonErrorKind::OutOfNodes => "not enough nodes for the document",
+            JsonErrorKind::DuplicateKey => "duplicate key",
+            JsonErrorKind::TooManyKeys => "too many keys to check for duplicates",
+        })
+    }
+}
+
+/// What the tokenizer was looking for when an error occurred.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expected {
+    Value,
+    PropertyName,
+    Character(u8),
+    /// A `,` or the given closing character.
+    CommaOr(u8),
+    Digit,
+    HexDigit,
+    Escape,
+    LowSurrogate,
+    Keyword(&'static str),
+    End,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expected::Value => f.write_str("a value"),
+            Expected::PropertyName => f.write_str("a property name"),
+            Expected::Character(c) => write!(f, "`{}`", c as char),
+            Expected::CommaOr(c) => write!(f, "`,` or `{}`", c as char),
+            Expected::Digit => f.write_str("a digit"),
+            Expected::HexDigit => f.write_str("a hexadecimal digit"),
+            Expected::Escape => f.write_str("an escape sequence"),
+            Expected::LowSurrogate => f.write_str("a low surrogate"),
+            Expected::Keyword(keyword) => write!(f, "`{}`", keyword),
+            Expected::End => f.write_str("the end of the input"),
+        }
+    }
+}
+
+/// A tokenizing error, with the position where it happened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JsonError {
+    pub kind: JsonErrorKind,
+    /// Byte offset in the input.
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in bytes.
+    pub column: usize,
+    /// The offending byte, or `None` at the end of the input.
+    pub found: Option<u8>,
+    pub expected: Option<Expected>,
+}
+
+impl JsonError {
+    /// Builds an error about the byte at `offset` in `buffer`, computing the
+    /// line and column.
+    pub fn at(buffer: &[u8],
+              offset: usize,
+              kind: JsonErrorKind,
+              expected: Option<Expected>)
+              -> JsonError {
+        let before = &buffer[..offset];
+        let line_start = before.iter().rposition(|&c| c == b'\n').map_or(0, |pos| pos + 1);
+        JsonError {
+            kind: kind,
+            offset: offset,
+            line: 1 + before.iter().filter(|&&c| c == b'\n').count(),
+            column: 1 + offset - line_start,
+            found: buffer.get(offset).cloned(),
+            expected: expected,
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} at line {}, column {} (offset {})",
+               self.kind,
+               self.line,
+               self.column,
+               self.offset)?;
+        match self.found {
+            Some(c) if c >= 0x20 && c < 0x7f => write!(f, ": found '{}'", c as char)?,
+            Some(c) => write!(f, ": found byte 0x{:02x}", c)?,
+            None => {}
+        }
+        if let Some(expected) = self.expected {
+            write!(f, ", expected {}", expected)?;
+        }
+        Ok(())
+    }
 }
 
 impl From<JsonError> for () {
@@ -33,14 +339,61 @@ impl From<JsonError> for () {
     }
 }
 
-
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum TokenizerState {
     Start,
+    ObjectStart,
     ExpectProperty,
+    ExpectColon,
     ExpectValue,
-    AfterArray,
+    ArrayStart,
     InArray,
+    AfterValue,
+    Done,
+}
+
+/// Consumes the rest of a value whose first token is `token`.
+pub fn skip_value<'a>(token: JsonToken<'a>,
+                      tokenizer: &mut JsonTokenizer<'a>)
+                      -> Result<(), JsonError> {
+    let mut depth = match token {
+        JsonToken::StartObject | JsonToken::StartArray => 1,
+        _ => return Ok(()),
+    };
+    while depth > 0 {
+        match tokenizer.next_token()? {
+            JsonToken::StartObject | JsonToken::StartArray => depth += 1,
+            JsonToken::EndObject | JsonToken::EndArray => depth -= 1,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Bounds on the input accepted by a tokenizer, to protect against hostile
+/// or oversized documents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JsonLimits {
+    /// Maximum nesting of objects and arrays, at most `MAX_DEPTH`.
+    pub max_depth: usize,
+    /// Maximum length in bytes of a string or property name, as it appears
+    /// in the input.
+    pub max_string_len: usize,
+    /// Maximum length in bytes of a number.
+    pub max_number_len: usize,
+    /// Maximum number of tokens in the document, not counting `Done`.
+    pub max_tokens: usize,
+}
+
+impl Default for JsonLimits {
+    fn default() -> Self {
+        JsonLimits {
+            max_depth: MAX_DEPTH,
+            max_string_len: usize::MAX,
+            max_number_len: usize::MAX,
+            max_tokens: usize::MAX,
+        }
+    }
 }
 
 pub struct JsonTokenizer<'a> {
@@ -48,15 +401,161 @@ pub struct JsonTokenizer<'a> {
     len: usize,
     pos: usize,
     state: TokenizerState,
-    depth: usize,
+    stack: NestingStack,
+    strict: bool,
+    relaxed: bool,
+    raw_numbers: bool,
+    limits: JsonLimits,
+    // Number of tokens returned so far.
+    tokens: usize,
+    // Where the last token read starts.
+    token_start: usize,
+    // The state before the last token read, from which a stream tokenizer
+    // resumes once more input is available.
+    token_state: TokenizerState,
+    // Whether more input may follow the end of the buffer.
+    partial: bool,
+    // The result of `peek_token()`, returned by the next call to
+    // `next_token()`.
+    peeked: Option<Result<JsonToken<'a>, JsonError>>,
+    // Whether iterating has ended, on `Done` or after an error.
+    fused: bool,
+}
+
+// An error found while decoding an escape sequence, `offset` being relative
+// to the start of the decoded bytes.
+struct EscapeError {
+    kind: JsonErrorKind,
+    offset: usize,
+    expected: Expected,
+}
+
+fn escape_error(kind: JsonErrorKind, offset: usize, expected: Expected) -> EscapeError {
+    EscapeError {
+        kind: kind,
+        offset: offset,
+        expected: expected,
+    }
 }
 
-macro_rules! error_if_eof {
-    ($s:ident) => (
-        if $s.eof() {
-            return Err(JsonError::UnexpecteEof);
+fn hex_digit(c: u8) -> Option<u16> {
+    match c {
+        b'0'...b'9' => Some((c - b'0') as u16),
+        b'a'...b'f' => Some((c - b'a' + 10) as u16),
+        b'A'...b'F' => Some((c - b'A' + 10) as u16),
+        _ => None,
+    }
+}
+
+// Reads the 4 hex digits at `offset`, which follow a `\u`.
+fn read_code_unit(bytes: &[u8], offset: usize) -> Result<u16, EscapeError> {
+    let mut unit = 0;
+    for i in offset..offset + 4 {
+        if i >= bytes.len() {
+            return Err(escape_error(JsonErrorKind::UnexpecteEof, i, Expected::HexDigit));
         }
-    )
+        match hex_digit(bytes[i]) {
+            Some(digit) => unit = (unit << 4) | digit,
+            None => return Err(escape_error(JsonErrorKind::InvalidEscape, i, Expected::HexDigit)),
+        }
+    }
+    Ok(unit)
+}
+
+// Decodes the escape sequence at the start of `bytes`, just after the `\`.
+// Returns the character and the number of bytes used.
+fn decode_escape(bytes: &[u8]) -> Result<(char, usize), EscapeError> {
+    if bytes.is_empty() {
+        return Err(escape_error(JsonErrorKind::UnexpecteEof, 0, Expected::Escape));
+    }
+    let c = match bytes[0] {
+        b'"' => '"',
+        b'\\' => '\\',
+        b'/' => '/',
+        b'b' => '\u{8}',
+        b'f' => '\u{c}',
+        b'n' => '\n',
+        b'r' => '\r',
+        b't' => '\t',
+        b'u' => return decode_unicode_escape(bytes),
+        _ => return Err(escape_error(JsonErrorKind::InvalidEscape, 0, Expected::Escape)),
+    };
+    Ok((c, 1))
+}
+
+// Decodes a `uXXXX` escape, or a surrogate pair `uXXXX\uXXXX`.
+fn decode_unicode_escape(bytes: &[u8]) -> Result<(char, usize), EscapeError> {
+    let unit = read_code_unit(bytes, 1)?;
+    let (code, used) = match unit {
+        0xd800...0xdbff => {
+            // High surrogate, it must be followed by an escaped low surrogate.
+            for (i, &expected) in [b'\\', b'u'].iter().enumerate() {
+                let offset = 5 + i;
+                if offset >= bytes.len() {
+                    return Err(escape_error(JsonErrorKind::UnexpecteEof,
+                                            offset,
+                                            Expected::LowSurrogate));
+                }
+                if bytes[offset] != expected {
+                    return Err(escape_error(JsonErrorKind::InvalidSurrogate,
+                                            offset,
+                                            Expected::LowSurrogate));
+                }
+            }
+            let low = read_code_unit(bytes, 7)?;
+            if low < 0xdc00 || low > 0xdfff {
+                return Err(escape_error(JsonErrorKind::InvalidSurrogate,
+                                        7,
+                                        Expected::LowSurrogate));
+            }
+            (0x10000 + (((unit as u32) - 0xd800) << 10) + ((low as u32) - 0xdc00), 11)
+        }
+        0xdc00...0xdfff => {
+            return Err(escape_error(JsonErrorKind::InvalidSurrogate, 1, Expected::HexDigit))
+        }
+        _ => (unit as u32, 5),
+    };
+    match char::from_u32(code) {
+        Some(c) => Ok((c, used)),
+        None => Err(escape_error(JsonErrorKind::InvalidSurrogate, 1, Expected::HexDigit)),
+    }
+}
+
+fn is_digit(c: u8) -> bool {
+    c >= b'0' && c <= b'9'
+}
+
+// Whether `c` can be part of an unquoted property name.
+fn is_identifier(c: u8) -> bool {
+    match c {
+        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'_' | b'$' => true,
+        _ => false,
+    }
+}
+
+// Length of the part of an unterminated string that can be returned as a
+// fragment, without splitting an escape sequence or a UTF-8 sequence.
+fn fragment_len(bytes: &[u8]) -> usize {
+    let mut len = 0;
+    while len < bytes.len() {
+        let next = match bytes[len] {
+            b'\\' => {
+                match decode_escape(&bytes[len + 1..]) {
+                    Ok((_, used)) => len + 1 + used,
+                    Err(_) => break,
+                }
+            }
+            0xf0...0xff => len + 4,
+            0xe0...0xef => len + 3,
+            0xc0...0xdf => len + 2,
+            _ => len + 1,
+        };
+        if next > bytes.len() {
+            break;
+        }
+        len = next;
+    }
+    len
 }
 
 impl<'a> JsonTokenizer<'a> {
@@ -67,15 +566,97 @@ impl<'a> JsonTokenizer<'a> {
             len: buffer.len(),
             pos: 0,
             state: TokenizerState::Start,
-            depth: 0,
+            stack: NestingStack::new(),
+            strict: false,
+            relaxed: false,
+            raw_numbers: false,
+            limits: JsonLimits::default(),
+            tokens: 0,
+            token_start: 0,
+            token_state: TokenizerState::Start,
+            partial: false,
+            peeked: None,
+            fused: false,
         }
     }
 
+    /// In strict mode, documents that are not valid RFC 8259 JSON are
+    /// rejected: missing colons, trailing commas, unescaped control
+    /// characters in strings and anything but whitespace after the root value.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// In relaxed mode, meant for configuration files written by hand, `//`
+    /// and `/* */` comments are skipped like whitespace, trailing commas are
+    /// accepted and property names may be identifiers without quotes, such
+    /// as `{retries: 3}`. This is off by default, even in strict mode.
+    pub fn relaxed(mut self, relaxed: bool) -> Self {
+        self.relaxed = relaxed;
+        self
+    }
+
+    /// Numbers with a fraction or an exponent, and integers that don't fit in
+    /// an `i64`, are returned as `RawNumber` tokens holding their text instead
+    /// of being converted to `f64`. They can then be read exactly with
+    /// `Decimal::parse()`, or with `parse_f32()` on targets where the `f64`
+    /// parser of `core` is too big.
+    pub fn raw_numbers(mut self, raw_numbers: bool) -> Self {
+        self.raw_numbers = raw_numbers;
+        self
+    }
+
+    /// Sets the limits checked while tokenizing. Exceeding one of them is
+    /// reported as a `NestingTooDeep`, `StringTooLong`, `NumberTooLong` or
+    /// `TooManyTokens` error.
+    pub fn limits(mut self, limits: JsonLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The input being tokenized.
+    pub fn input(&self) -> &'a [u8] {
+        self.buffer
+    }
+
+    /// Byte offset of the next character to read.
+    pub fn offset(&self) -> usize {
+        self.pos
+    }
+
+    /// Byte offset of the first character of the last token returned.
+    pub fn token_offset(&self) -> usize {
+        self.token_start
+    }
+
+    // What we are looking for in the current state.
+    fn expected(&self) -> Expected {
+        match self.state {
+            TokenizerState::ObjectStart |
+            TokenizerState::ExpectProperty => Expected::PropertyName,
+            TokenizerState::ExpectColon => Expected::Character(b':'),
+            TokenizerState::AfterValue => {
+                Expected::CommaOr(if self.stack.in_object() { b'}' } else { b']' })
+            }
+            _ => Expected::Value,
+        }
+    }
+
+    fn error_at(&self, offset: usize, kind: JsonErrorKind, expected: Expected) -> JsonError {
+        JsonError::at(self.buffer, offset, kind, Some(expected))
+    }
+
+    // Reports an error about the byte at the current position.
+    fn error(&self, kind: JsonErrorKind, expected: Expected) -> JsonError {
+        self.error_at(self.pos, kind, expected)
+    }
+
     fn eof(&self) -> bool {
         self.pos >= self.len
     }
 
-    fn next(&mut self) -> Result<u8, JsonError> {
+    fn next_byte(&mut self) -> Result<u8, JsonError> {
         let c = self.peek()?;
         self.pos += 1;
         Ok(c)
@@ -83,203 +664,897 @@ impl<'a> JsonTokenizer<'a> {
 
     fn peek(&mut self) -> Result<u8, JsonError> {
         if self.eof() {
-            return Err(JsonError::UnexpecteEof);
+            return Err(self.error(JsonErrorKind::UnexpecteEof, self.expected()));
         }
         let c = self.buffer[self.pos];
         Ok(c)
     }
 
-    fn eat_ws(&mut self) {
-        while let Ok(c) = self.next() {
-            if c != b' ' && c != b'\t' && c != b'\r' && c != b'\n' {
-                self.pos -= 1;
-                return;
+    fn eat_ws(&mut self) -> Result<(), JsonError> {
+        while !self.eof() {
+            match self.buffer[self.pos] {
+                b' ' | b'\t' | b'\r' | b'\n' => self.pos += 1,
+                b'/' if self.relaxed => self.eat_comment()?,
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    // Skips the comment starting at the current `/`, in relaxed mode.
+    fn eat_comment(&mut self) -> Result<(), JsonError> {
+        let start = self.pos;
+        let rest = &self.buffer[start + 1..self.len];
+        match rest.first() {
+            Some(&b'/') => {
+                self.pos = match rest.iter().position(|&c| c == b'\n') {
+                    Some(end) => start + 2 + end,
+                    // The comment may go on in the next chunk.
+                    None if self.partial => {
+                        let expected = self.expected();
+                        return Err(self.error_at(start, JsonErrorKind::UnexpecteEof, expected));
+                    }
+                    None => self.len,
+                };
+            }
+            Some(&b'*') => {
+                match rest[1..].windows(2).position(|end| end == b"*/") {
+                    Some(end) => self.pos = start + 2 + end + 2,
+                    None => {
+                        return Err(self.error_at(start,
+                                                 JsonErrorKind::UnexpecteEof,
+                                                 Expected::Character(b'/')))
+                    }
+                }
+            }
+            // More input may complete a `//` or a `/*`.
+            None if self.partial => {
+                return Err(self.error(JsonErrorKind::UnexpecteEof, self.expected()));
+            }
+            _ => {
+                let expected = self.expected();
+                return Err(self.error(JsonErrorKind::UnexpectedCharacter, expected));
             }
         }
+        Ok(())
     }
 
     // This consumes the delimiter.
-    fn advance_until(&mut self, what: u8) {
-        while let Ok(c) = self.next() {
-            if c == what {
-                return;
+    fn advance_until(&mut self, what: u8) -> Result<(), JsonError> {
+        while self.next_byte()? != what {}
+        Ok(())
+    }
+
+    // Reads a string whose opening `"` has already been consumed, validating
+    // escape sequences. This consumes the closing `"`.
+    fn read_string(&mut self) -> Result<JsonStr<'a>, JsonError> {
+        let buffer = self.buffer;
+        let start = self.pos;
+        let mut escaped = false;
+        loop {
+            if self.pos - start > self.limits.max_string_len {
+                return Err(self.error_at(start - 1,
+                                         JsonErrorKind::StringTooLong,
+                                         Expected::Character(b'"')));
+            }
+            if self.eof() {
+                return Err(self.error(JsonErrorKind::UnexpecteEof, Expected::Character(b'"')));
+            }
+            self.pos += 1;
+            match buffer[self.pos - 1] {
+                b'"' => break,
+                c if c < 0x20 && self.strict => {
+                    return Err(self.error_at(self.pos - 1,
+                                             JsonErrorKind::ControlCharacter,
+                                             Expected::Character(b'"')));
+                }
+                b'\\' => {
+                    match decode_escape(&buffer[self.pos..]) {
+                        Ok((_, used)) => self.pos += used,
+                        Err(err) => {
+                            return Err(self.error_at(self.pos + err.offset,
+                                                     err.kind,
+                                                     err.expected))
+                        }
+                    }
+                    escaped = true;
+                }
+                _ => {}
+            }
+        }
+        // An escape sequence may have gone past the limit just before the end.
+        if self.pos - 1 - start > self.limits.max_string_len {
+            return Err(self.error_at(start - 1,
+                                     JsonErrorKind::StringTooLong,
+                                     Expected::Character(b'"')));
+        }
+        match str::from_utf8(&buffer[start..self.pos - 1]) {
+            Ok(raw) => {
+                Ok(JsonStr {
+                    raw: raw,
+                    escaped: escaped,
+                })
+            }
+            Err(err) => {
+                Err(self.error_at(start + err.valid_up_to(),
+                                  JsonErrorKind::InvalidString,
+                                  Expected::Character(b'"')))
             }
         }
     }
 
-    fn as_literal(&self, slice: &[u8]) -> Result<JsonToken, JsonError> {
-        if let Ok(value) = str::from_utf8(slice) {
-            let s = String::from(value.trim());
-            Ok(if s == "null" {
-                JsonToken::Null
-            } else if s == "true" {
-                JsonToken::True
-            } else if s == "false" {
-                JsonToken::False
-            } else {
-                JsonToken::Literal(s)
-            })
+    // Consumes `what`, which has to be the next character.
+    fn expect_char(&mut self, what: u8) -> Result<(), JsonError> {
+        if self.peek_is(what) {
+            self.pos += 1;
+            return Ok(());
+        }
+        let kind = if self.eof() {
+            JsonErrorKind::UnexpecteEof
         } else {
-            Err(JsonError::InvalidString)
+            JsonErrorKind::UnexpectedCharacter
+        };
+        Err(self.error(kind, Expected::Character(what)))
+    }
+
+    fn peek_is(&self, what: u8) -> bool {
+        !self.eof() && self.buffer[self.pos] == what
+    }
+
+    // Consumes a run of digits, failing if there are none.
+    fn eat_digits(&mut self) -> Result<usize, JsonError> {
+        let start = self.pos;
+        while !self.eof() && is_digit(self.buffer[self.pos]) {
+            self.pos += 1;
         }
+        if self.pos == start {
+            let kind = if self.eof() {
+                JsonErrorKind::UnexpecteEof
+            } else {
+                JsonErrorKind::InvalidNumber
+            };
+            return Err(self.error(kind, Expected::Digit));
+        }
+        Ok(self.pos - start)
     }
 
-    fn in_array(&mut self) -> Result<JsonToken, JsonError> {
-        // Get the next value, which can be a Literal, an Object or an Array.
-        self.eat_ws();
-        error_if_eof!(self);
-        // Look for either a comma or the closing tag of the array.
+    fn read_number(&mut self) -> Result<JsonToken<'a>, JsonError> {
         let start = self.pos;
-        loop {
-            let c = self.next()?;
-            if c == b',' {
-                return self.as_literal(&self.buffer[start..self.pos - 1]);
-            } else if c == b']' {
-                if self.pos == start + 1 {
-                    // No new value.
-                    self.depth -= 1;
-                    self.state = TokenizerState::AfterArray;
-                    return Ok(JsonToken::EndArray);
-                } else {
-                    // Don't eat the array closing, and use what was scanned as the value.
-                    self.pos -= 1;
-                    return self.as_literal(&self.buffer[start..self.pos]);
+        let mut integer = true;
+        if self.peek_is(b'-') {
+            self.pos += 1;
+        }
+        let int_start = self.pos;
+        // No leading zeros, and at least one digit.
+        if self.eat_digits()? > 1 && self.buffer[int_start] == b'0' {
+            return Err(self.error_at(int_start, JsonErrorKind::InvalidNumber, Expected::Value));
+        }
+        if self.peek_is(b'.') {
+            self.pos += 1;
+            integer = false;
+            self.eat_digits()?;
+        }
+        if self.peek_is(b'e') || self.peek_is(b'E') {
+            self.pos += 1;
+            integer = false;
+            if self.peek_is(b'+') || self.peek_is(b'-') {
+                self.pos += 1;
+            }
+            self.eat_digits()?;
+        }
+
+        if self.pos - start > self.limits.max_number_len {
+            return Err(self.error_at(start, JsonErrorKind::NumberTooLong, Expected::Value));
+        }
+        if self.partial && self.eof() {
+            // The next chunk may have more digits.
+            return Err(self.error(JsonErrorKind::UnexpecteEof, Expected::Digit));
+        }
+
+        // Only ASCII characters have been accepted.
+        let text = unsafe { str::from_utf8_unchecked(&self.buffer[start..self.pos]) };
+        let token = if integer {
+            // The syntax has been checked already, so this can only fail on overflow.
+            match text.parse::<i64>() {
+                Ok(value) => Some(JsonToken::Integer(value)),
+                Err(_) if self.raw_numbers => Some(JsonToken::RawNumber(text)),
+                Err(_) => None,
+            }
+        } else if self.raw_numbers {
+            Some(JsonToken::RawNumber(text))
+        } else {
+            match text.parse::<f64>() {
+                Ok(value) if value != f64::INFINITY && value != f64::NEG_INFINITY => {
+                    Some(JsonToken::Number(value))
                 }
+                _ => None,
             }
+        };
+        match token {
+            Some(token) => Ok(token),
+            None => Err(self.error_at(start, JsonErrorKind::NumberOverflow, Expected::Value)),
         }
     }
 
-    fn start(&mut self) -> Result<JsonToken, JsonError> {
-        // We only support Objects and Arrays as top level constructs.
-        match self.next()? {
-            b'{' => {
-                self.state = TokenizerState::ExpectProperty;
-                self.depth += 1;
-                Ok(JsonToken::StartObject)
+    fn read_keyword(&mut self,
+                    keyword: &'static str,
+                    token: JsonToken<'a>)
+                    -> Result<JsonToken<'a>, JsonError> {
+        for expected in keyword.bytes() {
+            if self.eof() || self.buffer[self.pos] != expected {
+                let kind = if self.eof() {
+                    JsonErrorKind::UnexpecteEof
+                } else {
+                    JsonErrorKind::UnexpectedCharacter
+                };
+                return Err(self.error(kind, Expected::Keyword(keyword)));
             }
-            b'[' => {
-                self.state = TokenizerState::InArray;
-                self.depth += 1;
-                Ok(JsonToken::StartArray)
+            self.pos += 1;
+        }
+        Ok(token)
+    }
+
+    // Reads a string, number, `true`, `false` or `null`.
+    fn read_scalar(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        match self.peek()? {
+            b'"' => {
+                self.pos += 1;
+                Ok(JsonToken::String(self.read_string()?))
             }
-            _ => Err(JsonError::UnexpectedCharacter),
+            b'-' | b'0'...b'9' => self.read_number(),
+            b't' => self.read_keyword("true", JsonToken::True),
+            b'f' => self.read_keyword("false", JsonToken::False),
+            b'n' => self.read_keyword("null", JsonToken::Null),
+            _ => Err(self.error(JsonErrorKind::UnexpectedCharacter, Expected::Value)),
         }
     }
 
-    fn expect_value(&mut self) -> Result<JsonToken, JsonError> {
-        // Check if this value is an Object, an Array or a Literal
-        match self.next()? {
+    // Called once a complete value has been read.
+    fn value_done(&mut self) {
+        self.state = if self.stack.depth == 0 {
+            TokenizerState::Done
+        } else {
+            TokenizerState::AfterValue
+        };
+    }
+
+    // Enters an object or an array.
+    fn push(&mut self, object: bool) -> Result<(), JsonError> {
+        if self.stack.depth >= self.limits.max_depth || self.stack.push(object).is_err() {
+            return Err(self.error(JsonErrorKind::NestingTooDeep, Expected::Value));
+        }
+        Ok(())
+    }
+
+    fn read_value(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        // Check if this value is an Object, an Array or a scalar.
+        match self.peek()? {
             b'{' => {
-                self.state = TokenizerState::ExpectProperty;
-                self.depth += 1;
+                self.push(true)?;
+                self.pos += 1;
+                self.state = TokenizerState::ObjectStart;
                 Ok(JsonToken::StartObject)
             }
             b'[' => {
-                self.state = TokenizerState::InArray;
-                self.depth += 1;
+                self.push(false)?;
+                self.pos += 1;
+                self.state = TokenizerState::ArrayStart;
                 Ok(JsonToken::StartArray)
             }
-            b'}' => {
-                self.eat_ws();
-                if !self.eof() && self.next()? != b',' {
-                    return Err(JsonError::UnexpectedCharacter);
-                }
-                self.state = TokenizerState::ExpectProperty;
-                self.depth -= 1;
-                Ok(JsonToken::EndObject)
-            }
             _ => {
-                // It's a Literal.
-                let start = self.pos - 1;
-                let end;
-                // We need to reach either a `,` or a `}`
-                loop {
-                    let c = self.next()?;
-                    if c == b',' {
-                        end = self.pos - 1;
-                        self.state = TokenizerState::ExpectProperty;
-                        break;
-                    }
-                    if c == b'}' {
-                        self.pos -= 1;
-                        end = self.pos;
-                        break;
-                    }
-                }
+                let token = self.read_scalar()?;
+                self.value_done();
+                Ok(token)
+            }
+        }
+    }
 
-                self.as_literal(&self.buffer[start..end])
+    // Consumes the `}` or `]` closing the innermost container.
+    fn close_container(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        let token = match self.peek()? {
+            b'}' if self.stack.in_object() => JsonToken::EndObject,
+            b']' if !self.stack.in_object() => JsonToken::EndArray,
+            _ => {
+                let expected = self.expected();
+                return Err(self.error(JsonErrorKind::UnexpectedCharacter, expected));
             }
+        };
+        self.pos += 1;
+        self.stack.pop();
+        self.value_done();
+        Ok(token)
+    }
+
+    // Reads an unquoted property name, in relaxed mode.
+    fn read_identifier(&mut self) -> Result<JsonStr<'a>, JsonError> {
+        let start = self.pos;
+        while !self.eof() && is_identifier(self.buffer[self.pos]) {
+            self.pos += 1;
+        }
+        if self.eof() && self.partial {
+            // The name may go on in the next chunk.
+            return Err(self.error(JsonErrorKind::UnexpecteEof, Expected::Character(b':')));
+        }
+        if self.pos - start > self.limits.max_string_len {
+            return Err(self.error_at(start, JsonErrorKind::StringTooLong, Expected::PropertyName));
         }
+        // Identifiers are ASCII without any `\`.
+        let raw = unsafe { str::from_utf8_unchecked(&self.buffer[start..self.pos]) };
+        Ok(JsonStr {
+            raw: raw,
+            escaped: false,
+        })
     }
 
-    fn expect_property(&mut self) -> Result<JsonToken, JsonError> {
-        self.eat_ws();
+    fn expect_property(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        let name = match self.peek()? {
+            b'}' if self.state == TokenizerState::ExpectProperty && self.strict &&
+                    !self.relaxed => {
+                return Err(self.error(JsonErrorKind::TrailingComma, Expected::PropertyName));
+            }
+            b'}' => return self.close_container(),
+            b'"' => {
+                self.pos += 1;
+                self.read_string()?
+            }
+            c if self.relaxed && is_identifier(c) && !is_digit(c) => self.read_identifier()?,
+            // If the first character is no a `"` something is wrong.
+            _ => return Err(self.error(JsonErrorKind::UnexpectedCharacter, Expected::PropertyName)),
+        };
+        self.state = TokenizerState::ExpectColon;
+        Ok(JsonToken::PropertyName(name))
+    }
 
-        let c = self.next()?;
-        // If the first character is no a `"` something is wrong.
-        if c != b'"' {
-            return Err(JsonError::UnexpectedCharacter);
+    // Reads the `:` after a property name, and the value that follows.
+    fn expect_colon(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        if self.strict {
+            self.expect_char(b':')?;
+        } else {
+            self.advance_until(b':')?;
         }
-        // Look for the next `"` and for a `:`
-        let start = self.pos;
-        self.advance_until(b'"');
-        error_if_eof!(self);
-        let value = str::from_utf8(&self.buffer[start..self.pos - 1]);
-        if value.is_err() {
-            return Err(JsonError::InvalidString);
-        }
-        // Look for the `:`
-        self.eat_ws();
-        self.advance_until(b':');
-        self.eat_ws();
-        error_if_eof!(self);
         self.state = TokenizerState::ExpectValue;
-        Ok(JsonToken::PropertyName(String::from(value.unwrap())))
+        self.read_token()
+    }
+
+    fn in_array(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        // Get the next value, or the end of the array.
+        if self.peek()? == b']' {
+            if self.state == TokenizerState::InArray && self.strict && !self.relaxed {
+                return Err(self.error(JsonErrorKind::TrailingComma, Expected::Value));
+            }
+            return self.close_container();
+        }
+        self.read_value()
+    }
+
+    fn after_value(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        // We need either a `,` or the end of the current container.
+        if self.peek()? != b',' {
+            return self.close_container();
+        }
+        self.pos += 1;
+        self.state = if self.stack.in_object() {
+            TokenizerState::ExpectProperty
+        } else {
+            TokenizerState::InArray
+        };
+        self.read_token()
+    }
+
+    /// Returns the next token of the document, whose root can be any JSON
+    /// value. `Done` is returned once the root value is complete, and on
+    /// every call after that.
+    ///
+    /// Empty or whitespace-only input has no tokens: the first call returns
+    /// `Done`, or an `UnexpecteEof` error in strict mode.
+    pub fn next_token(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        if let Some(peeked) = self.peeked.take() {
+            return peeked;
+        }
+        let token = self.read_token()?;
+        if token != JsonToken::Done {
+            if self.tokens >= self.limits.max_tokens {
+                let start = self.token_start;
+                return Err(self.error_at(start, JsonErrorKind::TooManyTokens, Expected::End));
+            }
+            self.tokens += 1;
+        }
+        Ok(token)
     }
 
-    pub fn next_token(&mut self) -> Result<JsonToken, JsonError> {
-        // println!("next_token state={:?} pos={} depth={}",
-        //          self.state,
-        //          self.pos,
-        //          self.depth);
-        self.eat_ws();
-        if self.depth == 0 && self.state != TokenizerState::Start {
+    fn read_token(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        // This stops at the start of an incomplete comment.
+        let ws = self.eat_ws();
+        self.token_start = self.pos;
+        self.token_state = self.state;
+        ws?;
+        if self.state == TokenizerState::Done {
+            if self.strict && !self.eof() {
+                return Err(self.error(JsonErrorKind::TrailingContent, Expected::End));
+            }
             return Ok(JsonToken::Done);
         }
 
         if self.eof() && self.state == TokenizerState::Start {
+            // An empty document has no tokens, but isn't valid JSON.
+            if self.partial || self.strict {
+                return Err(self.error(JsonErrorKind::UnexpecteEof, Expected::Value));
+            }
             return Ok(JsonToken::Done);
         }
-        error_if_eof!(self);
 
         match self.state {
-            TokenizerState::Start => self.start(),
+            TokenizerState::Start |
+            TokenizerState::ExpectValue => self.read_value(),
+            TokenizerState::ObjectStart |
             TokenizerState::ExpectProperty => self.expect_property(),
-            TokenizerState::ExpectValue => self.expect_value(),
+            TokenizerState::ArrayStart |
             TokenizerState::InArray => self.in_array(),
-            TokenizerState::AfterArray => {
-                self.eat_ws();
-                error_if_eof!(self);
-                match self.next()? {
-                    b'}' => {
-                        self.depth -= 1;
-                        self.eat_ws();
-                        if !self.eof() {
-                            let c = self.next()?;
-                            if c != b',' {
-                                return Err(JsonError::UnexpectedCharacter);
-                            }
-                        }
-                        self.state = TokenizerState::ExpectProperty;
-                        Ok(JsonToken::EndObject)
+            TokenizerState::ExpectColon => self.expect_colon(),
+            TokenizerState::AfterValue => self.after_value(),
+            TokenizerState::Done => Ok(JsonToken::Done),
+        }
+    }
+
+    /// Returns the token the next call to `next_token()` will return, without
+    /// consuming it. `offset()` and `token_offset()` already refer to it.
+    pub fn peek_token(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        if let Some(peeked) = self.peeked {
+            return peeked;
+        }
+        let peeked = self.next_token();
+        self.peeked = Some(peeked);
+        peeked
+    }
+
+    /// Consumes the next value, objects and arrays included. When the next
+    /// token is a property name, the whole member is consumed: the name and
+    /// its value. Nothing is consumed at the end of an object, array or
+    /// document, where there is no value to skip. Whether the next token has
+    /// been peeked makes no difference.
+    pub fn skip_value(&mut self) -> Result<(), JsonError> {
+        match self.peek_token()? {
+            JsonToken::EndObject | JsonToken::EndArray | JsonToken::Done => Ok(()),
+            JsonToken::PropertyName(_) => {
+                self.next_token()?;
+                self.skip_value()
+            }
+            _ => {
+                let token = self.next_token()?;
+                skip_value(token, self)
+            }
+        }
+    }
+
+    /// Consumes the rest of the innermost object or array that has been
+    /// started, its `EndObject` or `EndArray` token included. Before the
+    /// root value has started, this consumes the whole document. Whether
+    /// the next token has been peeked makes no difference.
+    pub fn skip_container(&mut self) -> Result<(), JsonError> {
+        loop {
+            match self.peek_token()? {
+                JsonToken::EndObject | JsonToken::EndArray => {
+                    self.next_token()?;
+                    return Ok(());
+                }
+                JsonToken::Done => return Ok(()),
+                _ => self.skip_value()?,
+            }
+        }
+    }
+
+    /// Same as `next_token()`, but copies and decodes the strings.
+    pub fn next_owned_token(&mut self) -> Result<OwnedJsonToken, JsonError> {
+        self.next_token().map(JsonToken::into_owned)
+    }
+}
+
+/// Iterates over the tokens of the document until `Done`, which isn't
+/// returned, or until the first error.
+impl<'a> Iterator for JsonTokenizer<'a> {
+    type Item = Result<JsonToken<'a>, JsonError>;
+
+    fn next(&mut self) -> Option<Result<JsonToken<'a>, JsonError>> {
+        if self.fused {
+            return None;
+        }
+        match self.next_token() {
+            Ok(JsonToken::Done) => {
+                self.fused = true;
+                None
+            }
+            Ok(token) => Some(Ok(token)),
+            Err(err) => {
+                self.fused = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// A tokenizer for input arriving in chunks, like an HTTP response body.
+///
+/// Input is copied into a caller provided buffer, which only needs to be
+/// large enough to hold the longest token: complete tokens are returned as
+/// soon as they are available and their bytes are then reused.
+pub struct JsonStreamTokenizer<'b> {
+    buffer: &'b mut [u8],
+    // The unconsumed input is `buffer[start..end]`.
+    start: usize,
+    end: usize,
+    state: TokenizerState,
+    stack: NestingStack,
+    strict: bool,
+    relaxed: bool,
+    raw_numbers: bool,
+    lines: bool,
+    fragments: bool,
+    limits: JsonLimits,
+    tokens: usize,
+    finished: bool,
+    // In JSON Lines mode, whether the rest of the current line is ignored.
+    skip_line: bool,
+    // Whether the fragments of a string are being returned, its opening `"`
+    // being consumed, and the length of the ones returned so far.
+    in_string: bool,
+    string_len: usize,
+    // Position of `buffer[0]` in the whole input, for error reporting.
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'b> JsonStreamTokenizer<'b> {
+    pub fn new(buffer: &'b mut [u8]) -> Self {
+        JsonStreamTokenizer {
+            buffer: buffer,
+            start: 0,
+            end: 0,
+            state: TokenizerState::Start,
+            stack: NestingStack::new(),
+            strict: false,
+            relaxed: false,
+            raw_numbers: false,
+            lines: false,
+            fragments: false,
+            limits: JsonLimits::default(),
+            tokens: 0,
+            finished: false,
+            skip_line: false,
+            in_string: false,
+            string_len: 0,
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// See `JsonTokenizer::strict()`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// See `JsonTokenizer::relaxed()`. A comment has to fit in the buffer.
+    pub fn relaxed(mut self, relaxed: bool) -> Self {
+        self.relaxed = relaxed;
+        self
+    }
+
+    /// See `JsonTokenizer::raw_numbers()`.
+    pub fn raw_numbers(mut self, raw_numbers: bool) -> Self {
+        self.raw_numbers = raw_numbers;
+        self
+    }
+
+    /// In JSON Lines mode, the input holds one document per line, like a batch
+    /// of records read by `JsonLines` but without having it all in RAM. `Done`
+    /// is returned at the end of each document and blank lines are skipped.
+    /// After an error the rest of the line is skipped, and the next call
+    /// starts over with the document on the next line. Once `finish()` has
+    /// been called, `None` means that all the input has been read.
+    ///
+    /// The limits apply to each line.
+    pub fn lines(mut self, lines: bool) -> Self {
+        self.lines = lines;
+        self
+    }
+
+    /// In string fragment mode, a string value too long for the buffer is
+    /// returned in parts instead of failing with `TokenTooLong`: a
+    /// `StartString` token, `StringFragment` tokens and `EndString`. This is
+    /// meant for binary data such as a firmware image, see
+    /// `BinaryDecoder::feed()`. Shorter strings are still returned as
+    /// `String` tokens, and property names have to fit in the buffer.
+    ///
+    /// Escape sequences are never split, so the buffer has to hold at least
+    /// 12 bytes.
+    pub fn string_fragments(mut self, fragments: bool) -> Self {
+        self.fragments = fragments;
+        self
+    }
+
+    /// See `JsonTokenizer::limits()`.
+    pub fn limits(mut self, limits: JsonLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // Starts over with a new document.
+    fn reset(&mut self) {
+        self.state = TokenizerState::Start;
+        self.stack = NestingStack::new();
+        self.tokens = 0;
+        self.in_string = false;
+    }
+
+    // Whether the token at `start` is a string value, rather than a property
+    // name.
+    fn at_string_value(&self) -> bool {
+        let value = match self.state {
+            TokenizerState::Start |
+            TokenizerState::ExpectValue |
+            TokenizerState::ArrayStart |
+            TokenizerState::InArray => true,
+            _ => false,
+        };
+        value && self.buffer[self.start] == b'"'
+    }
+
+    // Returns the next part of a string returned in fragments, or its end.
+    fn next_fragment(&mut self, end: usize, partial: bool) -> Result<Option<JsonToken>, JsonError> {
+        if self.start < end && self.buffer[self.start] == b'"' {
+            self.start += 1;
+            self.in_string = false;
+            self.state = if self.stack.depth == 0 {
+                TokenizerState::Done
+            } else {
+                TokenizerState::AfterValue
+            };
+            return Ok(Some(JsonToken::EndString));
+        }
+        // This validates the escape sequences, and the limit is checked here.
+        let mut tokenizer = JsonTokenizer {
+            buffer: &self.buffer[..end],
+            len: end,
+            pos: self.start,
+            state: self.state,
+            stack: self.stack,
+            strict: self.strict,
+            relaxed: self.relaxed,
+            raw_numbers: self.raw_numbers,
+            limits: JsonLimits { max_string_len: usize::MAX, ..self.limits },
+            tokens: self.tokens,
+            token_start: self.start,
+            token_state: self.state,
+            partial: partial,
+            peeked: None,
+            fused: false,
+        };
+        let raw = match tokenizer.read_string() {
+            // The closing `"` is left for `EndString`.
+            Ok(value) => value.raw,
+            Err(ref err) if err.kind == JsonErrorKind::UnexpecteEof && partial => {
+                let bytes = &self.buffer[self.start..end];
+                match str::from_utf8(&bytes[..fragment_len(bytes)]) {
+                    Ok(raw) => raw,
+                    Err(err) => {
+                        let err = JsonError::at(&self.buffer[..end],
+                                                self.start + err.valid_up_to(),
+                                                JsonErrorKind::InvalidString,
+                                                Some(Expected::Character(b'"')));
+                        self.skip_line = self.lines;
+                        return Err(self.locate(err));
+                    }
+                }
+            }
+            Err(err) => {
+                self.skip_line = self.lines;
+                return Err(self.locate(err));
+            }
+        };
+        if raw.is_empty() {
+            if self.start == 0 && self.end == self.buffer.len() {
+                let err = JsonError::at(&self.buffer[..],
+                                        self.start,
+                                        JsonErrorKind::TokenTooLong,
+                                        None);
+                self.skip_line = self.lines;
+                return Err(self.locate(err));
+            }
+            return Ok(None);
+        }
+        self.string_len += raw.len();
+        if self.string_len > self.limits.max_string_len {
+            let err = JsonError::at(&self.buffer[..end],
+                                    self.start,
+                                    JsonErrorKind::StringTooLong,
+                                    Some(Expected::Character(b'"')));
+            self.skip_line = self.lines;
+            return Err(self.locate(err));
+        }
+        self.start += raw.len();
+        Ok(Some(JsonToken::StringFragment(JsonStr::new(raw))))
+    }
+
+    // Where the current line ends in the buffer, if its `\n` is there.
+    fn line_end(&self) -> Option<usize> {
+        self.buffer[self.start..self.end].iter().position(|&c| c == b'\n').map(|i| self.start + i)
+    }
+
+    // In JSON Lines mode, moves to the start of the next document. This
+    // returns `false` if more input is needed to find it.
+    fn next_line(&mut self) -> bool {
+        loop {
+            let newline = self.line_end();
+            if self.skip_line {
+                match newline {
+                    Some(newline) => {
+                        self.start = newline + 1;
+                        self.skip_line = false;
+                        self.reset();
                     }
-                    b',' => {
-                        self.state = TokenizerState::ExpectProperty;
-                        self.next_token()
+                    None => {
+                        self.start = self.end;
+                        return false;
+                    }
+                }
+                continue;
+            }
+            if self.state != TokenizerState::Start {
+                return true;
+            }
+            let end = newline.unwrap_or(self.end);
+            let blank = self.buffer[self.start..end]
+                .iter()
+                .all(|&c| c == b' ' || c == b'\t' || c == b'\r');
+            if !blank {
+                return true;
+            }
+            // A blank line, or the start of one.
+            match newline {
+                Some(newline) => self.start = newline + 1,
+                None => {
+                    self.start = self.end;
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Appends input, returning how many bytes of `data` fit in the buffer.
+    /// The rest has to be fed again once some tokens have been read.
+    pub fn feed(&mut self, data: &[u8]) -> usize {
+        if self.start > 0 {
+            // Move the pending input to the front of the buffer.
+            for &c in &self.buffer[..self.start] {
+                if c == b'\n' {
+                    self.line += 1;
+                    self.column = 1;
+                } else {
+                    self.column += 1;
+                }
+            }
+            self.offset += self.start;
+            for i in self.start..self.end {
+                self.buffer[i - self.start] = self.buffer[i];
+            }
+            self.end -= self.start;
+            self.start = 0;
+        }
+        let len = cmp::min(data.len(), self.buffer.len() - self.end);
+        self.buffer[self.end..self.end + len].copy_from_slice(&data[..len]);
+        self.end += len;
+        len
+    }
+
+    /// Signals that all the input has been fed.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Returns the next token, or `None` if more input is needed to complete
+    /// it. Once `finish()` has been called this never returns `None`, except
+    /// in JSON Lines mode.
+    pub fn next_token(&mut self) -> Result<Option<JsonToken>, JsonError> {
+        let (end, partial) = if self.lines {
+            if !self.next_line() {
+                return Ok(None);
+            }
+            // Each line is tokenized as a whole document.
+            match self.line_end() {
+                Some(newline) => (newline, false),
+                None => (self.end, !self.finished),
+            }
+        } else {
+            (self.end, !self.finished)
+        };
+        if self.in_string {
+            return self.next_fragment(end, partial);
+        }
+        let mut tokenizer = JsonTokenizer {
+            buffer: &self.buffer[..end],
+            len: end,
+            pos: self.start,
+            state: self.state,
+            stack: self.stack,
+            strict: self.strict,
+            relaxed: self.relaxed,
+            raw_numbers: self.raw_numbers,
+            limits: self.limits,
+            tokens: self.tokens,
+            token_start: self.start,
+            token_state: self.state,
+            partial: partial,
+            peeked: None,
+            fused: false,
+        };
+        match tokenizer.next_token() {
+            Ok(JsonToken::Done) if self.lines => {
+                if partial && self.strict {
+                    // Anything but whitespace before the end of the line is
+                    // an error, which has to wait for more input.
+                    self.start = tokenizer.pos;
+                    self.state = tokenizer.state;
+                    return Ok(None);
+                }
+                self.skip_line = true;
+                Ok(Some(JsonToken::Done))
+            }
+            Ok(token) => {
+                self.start = tokenizer.pos;
+                self.state = tokenizer.state;
+                self.stack = tokenizer.stack;
+                self.tokens = tokenizer.tokens;
+                Ok(Some(token))
+            }
+            Err(ref err) if err.kind == JsonErrorKind::UnexpecteEof && partial => {
+                // Whitespace and comments before the incomplete token are
+                // consumed, so that they don't have to fit in the buffer.
+                self.start = tokenizer.token_start;
+                self.state = tokenizer.token_state;
+                if self.start == 0 && self.end == self.buffer.len() && self.fragments &&
+                   self.at_string_value() {
+                    if self.tokens >= self.limits.max_tokens {
+                        let err = JsonError::at(&self.buffer[..],
+                                                self.start,
+                                                JsonErrorKind::TooManyTokens,
+                                                Some(Expected::End));
+                        return Err(self.locate(err));
                     }
-                    _ => Err(JsonError::UnexpectedCharacter),
+                    // The fragments follow the opening `"`.
+                    self.tokens += 1;
+                    self.start += 1;
+                    self.in_string = true;
+                    self.string_len = 0;
+                    return Ok(Some(JsonToken::StartString));
                 }
+                if self.start == 0 && self.end == self.buffer.len() {
+                    let err = JsonError::at(&self.buffer[..],
+                                            self.start,
+                                            JsonErrorKind::TokenTooLong,
+                                            None);
+                    self.skip_line = self.lines;
+                    return Err(self.locate(err));
+                }
+                // We'll start over from the token with more input.
+                Ok(None)
+            }
+            Err(err) => {
+                self.skip_line = self.lines;
+                Err(self.locate(err))
             }
         }
     }
+
+    // Turns an error located in the buffer into one located in the whole input.
+    fn locate(&self, err: JsonError) -> JsonError {
+        let mut err = err;
+        err.offset += self.offset;
+        if err.line == 1 {
+            err.column += self.column - 1;
+        }
+        err.line += self.line - 1;
+        err
+    }
 }
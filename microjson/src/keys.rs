@@ -0,0 +1,141 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Detection of repeated property names. RFC 8259 leaves their meaning to
+// the implementation, so documents that act on the device reject them.
+
+use json::{Expected, JsonError, JsonErrorKind, JsonStr, JsonToken, JsonTokenizer};
+
+/// Checks the tokens of a document for objects with a repeated property
+/// name. The names of the open objects are remembered in a caller provided
+/// table, declared as `[None; N]`, whose size bounds the number of members
+/// of an object plus the number of objects it is nested in.
+pub struct DuplicateKeys<'k, 'a: 'k> {
+    // The names of the open objects, each object starting with `None`.
+    keys: &'k mut [Option<JsonStr<'a>>],
+    len: usize,
+}
+
+impl<'k, 'a> DuplicateKeys<'k, 'a> {
+    pub fn new(keys: &'k mut [Option<JsonStr<'a>>]) -> Self {
+        DuplicateKeys {
+            keys: keys,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self,
+            key: Option<JsonStr<'a>>,
+            tokenizer: &JsonTokenizer<'a>)
+            -> Result<(), JsonError> {
+        if self.len == self.keys.len() {
+            return Err(JsonError::at(tokenizer.input(),
+                                     tokenizer.token_offset(),
+                                     JsonErrorKind::TooManyKeys,
+                                     None));
+        }
+        self.keys[self.len] = key;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Checks `token`, the last token returned by `tokenizer`. A repeated
+    /// name is reported at its second occurrence.
+    pub fn check(&mut self,
+                 token: JsonToken<'a>,
+                 tokenizer: &JsonTokenizer<'a>)
+                 -> Result<(), JsonError> {
+        match token {
+            JsonToken::StartObject => self.push(None, tokenizer),
+            JsonToken::EndObject => {
+                while self.len > 0 {
+                    self.len -= 1;
+                    if self.keys[self.len].is_none() {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            JsonToken::PropertyName(name) => {
+                let duplicate = self.keys[..self.len]
+                    .iter()
+                    .rev()
+                    .take_while(|key| key.is_some())
+                    .any(|key| key.map_or(false, |key| key == name));
+                if duplicate {
+                    return Err(JsonError::at(tokenizer.input(),
+                                             tokenizer.token_offset(),
+                                             JsonErrorKind::DuplicateKey,
+                                             Some(Expected::PropertyName)));
+                }
+                self.push(Some(name), tokenizer)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the next token of `tokenizer` and checks it.
+    pub fn next_token(&mut self,
+                      tokenizer: &mut JsonTokenizer<'a>)
+                      -> Result<JsonToken<'a>, JsonError> {
+        let token = tokenizer.next_token()?;
+        self.check(token, tokenizer)?;
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use json::{JsonErrorKind, JsonStr, JsonToken, JsonTokenizer};
+    use keys::DuplicateKeys;
+
+    fn check(text: &str, width: usize) -> Result<(), (JsonErrorKind, usize)> {
+        let mut keys = [None; 8];
+        let mut duplicates = DuplicateKeys::new(&mut keys[..width]);
+        let mut tokenizer = JsonTokenizer::new(text);
+        loop {
+            match duplicates.next_token(&mut tokenizer) {
+                Ok(JsonToken::Done) => return Ok(()),
+                Ok(_) => {}
+                Err(err) => return Err((err.kind, err.offset)),
+            }
+        }
+    }
+
+    #[test]
+    fn duplicates() {
+        assert_eq!(check(r#"{"a": 1, "b": {"a": 2, "b": [{"a": 3}]}, "c": {}}"#, 8), Ok(()));
+        assert_eq!(check(r#"[{"a": 1}, {"a": 2}]"#, 8), Ok(()));
+        assert_eq!(check(r#"{"a": 1, "b": 2, "a": 3}"#, 8),
+                   Err((JsonErrorKind::DuplicateKey, 17)));
+        assert_eq!(check(r#"{"a": {"b": 1, "b": 2}}"#, 8),
+                   Err((JsonErrorKind::DuplicateKey, 15)));
+        // Names are compared once decoded.
+        assert_eq!(check(r#"{"é": 1, "\u00e9": 2}"#, 8),
+                   Err((JsonErrorKind::DuplicateKey, 10)));
+        // Names are checked again after a nested object ends.
+        assert_eq!(check(r#"{"a": {"x": 1}, "a": 2}"#, 8),
+                   Err((JsonErrorKind::DuplicateKey, 16)));
+    }
+
+    #[test]
+    fn width() {
+        let text = r#"{"a": 1, "b": {"c": 2}, "d": 3}"#;
+        assert_eq!(check(text, 5), Ok(()));
+        assert_eq!(check(text, 4), Err((JsonErrorKind::TooManyKeys, 15)));
+    }
+
+    #[test]
+    fn key_matching() {
+        let keys = ["time", "isoDate", "é"];
+        assert_eq!(JsonStr::new("isoDate").find_in(&keys), Some(1));
+        assert_eq!(JsonStr::new(r#"\u0074ime"#).find_in(&keys), Some(0));
+        assert_eq!(JsonStr::new(r#"\u00e9"#).find_in(&keys), Some(2));
+        assert_eq!(JsonStr::new("times").find_in(&keys), None);
+        assert!(JsonStr::new(r#"a\nb"#).matches("a\nb"));
+        assert!(!JsonStr::new(r#"a\nb"#).matches(r#"a\nb"#));
+        assert!(!JsonStr::new(r#"\n"#).matches("\n\n\n"));
+    }
+}
@@ -5,32 +5,103 @@
 #![no_std]
 #![feature(collections)]
 
+#[macro_use]
 extern crate collections;
 
 pub mod json;
+mod stack;
+pub mod writer;
+#[macro_use]
+pub mod decode;
+pub mod binary;
+pub mod canonical;
+pub mod cbor;
+pub mod decimal;
+pub mod pointer;
+pub mod schema;
+pub mod dom;
+pub mod keys;
+pub mod lines;
+pub mod merge;
+pub mod visitor;
 
+pub use binary::*;
+pub use canonical::*;
+pub use cbor::*;
+pub use decimal::*;
+pub use decode::*;
+pub use dom::*;
 pub use json::*;
+pub use keys::*;
+pub use lines::*;
+pub use merge::*;
+pub use pointer::*;
+pub use schema::*;
+pub use visitor::*;
+pub use writer::*;
 
 #[cfg(test)]
 mod test {
 
-    use json::{JsonToken, JsonTokenizer};
+    use json::{Expected, JsonError, JsonErrorKind, JsonLimits, JsonStr, JsonStreamTokenizer,
+               JsonToken, JsonTokenizer, OwnedJsonToken, MAX_DEPTH};
     use collections::String;
+    use collections::string::ToString;
+    use collections::vec::Vec;
+    use core::cmp;
 
     macro_rules! s {
         ($t:expr) => (String::from($t))
     }
 
+    macro_rules! j {
+        ($t:expr) => (JsonStr::new($t))
+    }
+
+    // Feeds `text` to a stream tokenizer in chunks of `chunk_size` bytes.
+    fn stream_tokens(text: &str,
+                     chunk_size: usize,
+                     buffer: &mut [u8])
+                     -> Result<Vec<OwnedJsonToken>, JsonError> {
+        let mut tokenizer = JsonStreamTokenizer::new(buffer);
+        let mut tokens = Vec::new();
+        let mut input = text.as_bytes();
+        loop {
+            let fed = tokenizer.feed(&input[..cmp::min(chunk_size, input.len())]);
+            input = &input[fed..];
+            if input.is_empty() {
+                tokenizer.finish();
+            }
+            while let Some(token) = tokenizer.next_token()? {
+                if token == JsonToken::Done {
+                    return Ok(tokens);
+                }
+                tokens.push(token.into_owned());
+            }
+        }
+    }
+
+    // Runs the tokenizer until the first error.
+    fn first_error(mut tokenizer: JsonTokenizer) -> JsonError {
+        loop {
+            match tokenizer.next_token() {
+                Ok(JsonToken::Done) => panic!("The document should not parse"),
+                Ok(_) => {}
+                Err(error) => return error,
+            }
+        }
+    }
+
     #[test]
     fn simple_object() {
         let text = r#"{"time":1480556487,"isoDate":"2016-12-01T01:41:27Z"}"#;
         let mut tokenizer = JsonTokenizer::new(&text);
 
         let expected = [JsonToken::StartObject,
-                        JsonToken::PropertyName(s!("time")),
-                        JsonToken::Literal(s!("1480556487")),
-                        JsonToken::PropertyName(s!("isoDate")),
-                        JsonToken::Literal(s!("\"2016-12-01T01:41:27Z\"")),
+                        JsonToken::PropertyName(j!("time")),
+                        JsonToken::Integer(1480556487),
+                        JsonToken::PropertyName(j!("isoDate")),
+                        JsonToken::String(j!("2016-12-01T01:41:27Z")),
                         JsonToken::EndObject,
                         JsonToken::Done];
 
@@ -45,9 +116,9 @@ mod test {
         let mut tokenizer = JsonTokenizer::new(&text);
 
         let expected = [JsonToken::StartArray,
-                        JsonToken::Literal(s!("1")),
-                        JsonToken::Literal(s!("\"deux\"")),
-                        JsonToken::Literal(s!("42")),
+                        JsonToken::Integer(1),
+                        JsonToken::String(j!("deux")),
+                        JsonToken::Integer(42),
                         JsonToken::EndArray,
                         JsonToken::Done];
 
@@ -62,13 +133,13 @@ mod test {
         let mut tokenizer = JsonTokenizer::new(&text);
 
         let expected = [JsonToken::StartObject,
-                        JsonToken::PropertyName(s!("number")),
-                        JsonToken::Literal(s!("1480556487")),
-                        JsonToken::PropertyName(s!("array")),
+                        JsonToken::PropertyName(j!("number")),
+                        JsonToken::Integer(1480556487),
+                        JsonToken::PropertyName(j!("array")),
                         JsonToken::StartArray,
-                        JsonToken::Literal(s!("1")),
-                        JsonToken::Literal(s!("\"deux\"")),
-                        JsonToken::Literal(s!("3")),
+                        JsonToken::Integer(1),
+                        JsonToken::String(j!("deux")),
+                        JsonToken::Integer(3),
                         JsonToken::EndArray,
                         JsonToken::EndObject,
                         JsonToken::Done];
@@ -101,36 +172,36 @@ mod test {
 
         let expected =
             [JsonToken::StartObject,
-             JsonToken::PropertyName(s!("@iot.id")),
-             JsonToken::Literal(s!("\"1\"")),
-             JsonToken::PropertyName(s!("@iot.selfLink")),
-             JsonToken::Literal(s!("\"http://localhost:8080/v1.0/Datastreams(1)\"")),
-             JsonToken::PropertyName(s!("Thing@iot.navigationLink")),
-             JsonToken::Literal(s!("\"http://localhost:8080/v1.0/Datastreams(1)/Thing\"")),
-             JsonToken::PropertyName(s!("Sensor@iot.navigationLink")),
-             JsonToken::Literal(s!("\"http://localhost:8080/v1.0/Datastreams(1)/Sensor\"")),
-             JsonToken::PropertyName(s!("ObservedProperty@iot.navigationLink")),
-             JsonToken::Literal(s!("\"http://localhost:8080/v1.\
-                                    0/Datastreams(1)/ObservedProperty\"")),
-             JsonToken::PropertyName(s!("Observations@iot.navigationLink")),
-             JsonToken::Literal(s!("\"http://localhost:8080/v1.0/Datastreams(1)/Observations\"")),
-             JsonToken::PropertyName(s!("unitOfMeasurement")),
+             JsonToken::PropertyName(j!("@iot.id")),
+             JsonToken::String(j!("1")),
+             JsonToken::PropertyName(j!("@iot.selfLink")),
+             JsonToken::String(j!("http://localhost:8080/v1.0/Datastreams(1)")),
+             JsonToken::PropertyName(j!("Thing@iot.navigationLink")),
+             JsonToken::String(j!("http://localhost:8080/v1.0/Datastreams(1)/Thing")),
+             JsonToken::PropertyName(j!("Sensor@iot.navigationLink")),
+             JsonToken::String(j!("http://localhost:8080/v1.0/Datastreams(1)/Sensor")),
+             JsonToken::PropertyName(j!("ObservedProperty@iot.navigationLink")),
+             JsonToken::String(j!("http://localhost:8080/v1.\
+                                    0/Datastreams(1)/ObservedProperty")),
+             JsonToken::PropertyName(j!("Observations@iot.navigationLink")),
+             JsonToken::String(j!("http://localhost:8080/v1.0/Datastreams(1)/Observations")),
+             JsonToken::PropertyName(j!("unitOfMeasurement")),
              JsonToken::StartObject,
-             JsonToken::PropertyName(s!("name")),
-             JsonToken::Literal(s!("\"PM 2.5 Particulates (ug/m3)\"")),
-             JsonToken::PropertyName(s!("symbol")),
-             JsonToken::Literal(s!("\"μg/m³\"")),
-             JsonToken::PropertyName(s!("definition")),
-             JsonToken::Literal(s!("\"http://unitsofmeasure.org/ucum.html\"")),
+             JsonToken::PropertyName(j!("name")),
+             JsonToken::String(j!("PM 2.5 Particulates (ug/m3)")),
+             JsonToken::PropertyName(j!("symbol")),
+             JsonToken::String(j!("μg/m³")),
+             JsonToken::PropertyName(j!("definition")),
+             JsonToken::String(j!("http://unitsofmeasure.org/ucum.html")),
              JsonToken::EndObject,
-             JsonToken::PropertyName(s!("observationType")),
-             JsonToken::Literal(s!("\"http://www.opengis.net/def/observationType/OGC-OM/2.\
-                                    0/OM_Measurement\"")),
-             JsonToken::PropertyName(s!("description")),
-             JsonToken::Literal(s!("\"Air quality readings\"")),
-             JsonToken::PropertyName(s!("name")),
-             JsonToken::Literal(s!("\"air_quality_readings\"")),
-             JsonToken::PropertyName(s!("observedArea")),
+             JsonToken::PropertyName(j!("observationType")),
+             JsonToken::String(j!("http://www.opengis.net/def/observationType/OGC-OM/2.\
+                                    0/OM_Measurement")),
+             JsonToken::PropertyName(j!("description")),
+             JsonToken::String(j!("Air quality readings")),
+             JsonToken::PropertyName(j!("name")),
+             JsonToken::String(j!("air_quality_readings")),
+             JsonToken::PropertyName(j!("observedArea")),
              JsonToken::Null,
              JsonToken::EndObject,
              JsonToken::Done];
@@ -139,4 +210,885 @@ mod test {
             assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
         }
     }
+
+    #[test]
+    fn escaped_strings() {
+        let text = r#"{"say \"hi\"":"a\\b\/c\b\f\n\r\t","symbol":"\u03bcg/m\u00B3"}"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        let expected = [OwnedJsonToken::StartObject,
+                        OwnedJsonToken::PropertyName(s!("say \"hi\"")),
+                        OwnedJsonToken::String(s!("a\\b/c\u{8}\u{c}\n\r\t")),
+                        OwnedJsonToken::PropertyName(s!("symbol")),
+                        OwnedJsonToken::String(s!("μg/m³")),
+                        OwnedJsonToken::EndObject,
+                        OwnedJsonToken::Done];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_owned_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn delimiters_in_strings() {
+        let text = r#"["a,b", "c]", {"d}":"e,}"}]"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        let expected = [JsonToken::StartArray,
+                        JsonToken::String(j!("a,b")),
+                        JsonToken::String(j!("c]"))];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        let text = r#"["\ud83d\ude00", "\uD834\uDD1E"]"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        let expected = [OwnedJsonToken::StartArray,
+                        OwnedJsonToken::String(s!("😀")),
+                        OwnedJsonToken::String(s!("𝄞")),
+                        OwnedJsonToken::EndArray,
+                        OwnedJsonToken::Done];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_owned_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        let cases = [(r#"["\x"]"#, JsonErrorKind::InvalidEscape),
+                     (r#"["\u12g4"]"#, JsonErrorKind::InvalidEscape),
+                     (r#"["\ud83d"]"#, JsonErrorKind::InvalidSurrogate),
+                     (r#"["\ud83dA"]"#, JsonErrorKind::InvalidSurrogate),
+                     (r#"["\ude00"]"#, JsonErrorKind::InvalidSurrogate)];
+
+        for &(text, ref error) in cases.iter() {
+            let mut tokenizer = JsonTokenizer::new(text);
+            assert_eq!(tokenizer.next_token().unwrap(), JsonToken::StartArray);
+            assert_eq!(tokenizer.next_token().unwrap_err().kind, *error);
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        let text = r#"[0, -0, 42, -17, 9223372036854775807, -9223372036854775808, 1.5, -0.25,
+                       1e3, 2E-2, 6.02e+23, 0.0125]"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        let expected = [JsonToken::StartArray,
+                        JsonToken::Integer(0),
+                        JsonToken::Integer(0),
+                        JsonToken::Integer(42),
+                        JsonToken::Integer(-17),
+                        JsonToken::Integer(9223372036854775807),
+                        JsonToken::Integer(-9223372036854775808),
+                        JsonToken::Number(1.5),
+                        JsonToken::Number(-0.25),
+                        JsonToken::Number(1000.0),
+                        JsonToken::Number(0.02),
+                        JsonToken::Number(6.02e23),
+                        JsonToken::Number(0.0125),
+                        JsonToken::EndArray,
+                        JsonToken::Done];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn keywords() {
+        let text = r#"{"a":true,"b":false,"c":null}"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        let expected = [JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("a")),
+                        JsonToken::True,
+                        JsonToken::PropertyName(j!("b")),
+                        JsonToken::False,
+                        JsonToken::PropertyName(j!("c")),
+                        JsonToken::Null,
+                        JsonToken::EndObject,
+                        JsonToken::Done];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn scalar_roots() {
+        let cases = [("1480556487", JsonToken::Integer(1480556487)),
+                     (" -2.5 ", JsonToken::Number(-2.5)),
+                     ("\"2016-12-01T01:41:27Z\"", JsonToken::String(j!("2016-12-01T01:41:27Z"))),
+                     ("true", JsonToken::True),
+                     ("false", JsonToken::False),
+                     ("\nnull\n", JsonToken::Null)];
+
+        for &(text, token) in cases.iter() {
+            for &strict in [false, true].iter() {
+                let mut tokenizer = JsonTokenizer::new(text).strict(strict);
+                assert_eq!(tokenizer.next_token().unwrap(), token);
+                assert_eq!(tokenizer.next_token().unwrap(), JsonToken::Done);
+                assert_eq!(tokenizer.next_token().unwrap(), JsonToken::Done);
+            }
+        }
+
+        let mut buffer = [0u8; 16];
+        assert_eq!(stream_tokens("1480556487", 3, &mut buffer).unwrap(),
+                   vec![OwnedJsonToken::Integer(1480556487)]);
+        assert_eq!(first_error(JsonTokenizer::new("1 2").strict(true)).kind,
+                   JsonErrorKind::TrailingContent);
+        assert_eq!(first_error(JsonTokenizer::new("tru")).kind, JsonErrorKind::UnexpecteEof);
+    }
+
+    #[test]
+    fn empty_input() {
+        for text in ["", " ", "\r\n\t "].iter() {
+            let mut tokenizer = JsonTokenizer::new(text);
+            assert_eq!(tokenizer.next_token().unwrap(), JsonToken::Done);
+            assert_eq!(tokenizer.next_token().unwrap(), JsonToken::Done);
+
+            let error = first_error(JsonTokenizer::new(text).strict(true));
+            assert_eq!((error.kind, error.offset, error.expected),
+                       (JsonErrorKind::UnexpecteEof, text.len(), Some(Expected::Value)));
+
+            let mut buffer = [0u8; 8];
+            assert_eq!(stream_tokens(text, 1, &mut buffer).unwrap(), vec![]);
+        }
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        let cases = [("[01]", JsonErrorKind::InvalidNumber),
+                     ("[-]", JsonErrorKind::InvalidNumber),
+                     ("[-a]", JsonErrorKind::InvalidNumber),
+                     ("[1.]", JsonErrorKind::InvalidNumber),
+                     ("[.5]", JsonErrorKind::UnexpectedCharacter),
+                     ("[1e]", JsonErrorKind::InvalidNumber),
+                     ("[1e+]", JsonErrorKind::InvalidNumber),
+                     ("[+1]", JsonErrorKind::UnexpectedCharacter),
+                     ("[9223372036854775808]", JsonErrorKind::NumberOverflow),
+                     ("[-9223372036854775809]", JsonErrorKind::NumberOverflow),
+                     ("[1e400]", JsonErrorKind::NumberOverflow),
+                     ("[tru]", JsonErrorKind::UnexpectedCharacter),
+                     ("[nul1]", JsonErrorKind::UnexpectedCharacter)];
+
+        for &(text, ref error) in cases.iter() {
+            let mut tokenizer = JsonTokenizer::new(text);
+            assert_eq!(tokenizer.next_token().unwrap(), JsonToken::StartArray);
+            assert_eq!(tokenizer.next_token().unwrap_err().kind, *error);
+        }
+    }
+
+    #[test]
+    fn borrowed_strings() {
+        let text = r#"{"plain":"A\u0042C"}"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        assert_eq!(tokenizer.next_token().unwrap(), JsonToken::StartObject);
+        match tokenizer.next_token().unwrap() {
+            JsonToken::PropertyName(name) => {
+                assert_eq!(name.as_str(), Some("plain"));
+                assert!(name == "plain");
+            }
+            token => panic!("Unexpected token {:?}", token),
+        }
+        match tokenizer.next_token().unwrap() {
+            JsonToken::String(value) => {
+                assert!(value.has_escapes());
+                assert_eq!(value.as_str(), None);
+                assert_eq!(value.raw(), r"A\u0042C");
+                assert!(value == "ABC");
+                assert!(value != "AB");
+                assert_eq!(value, j!("ABC"));
+                assert_eq!(value.to_string(), s!("ABC"));
+            }
+            token => panic!("Unexpected token {:?}", token),
+        }
+    }
+
+    #[test]
+    fn array_of_objects() {
+        let text = r#"[{"a":1},{"b":[2]} , {}]"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        let expected = [JsonToken::StartArray,
+                        JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("a")),
+                        JsonToken::Integer(1),
+                        JsonToken::EndObject,
+                        JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("b")),
+                        JsonToken::StartArray,
+                        JsonToken::Integer(2),
+                        JsonToken::EndArray,
+                        JsonToken::EndObject,
+                        JsonToken::StartObject,
+                        JsonToken::EndObject,
+                        JsonToken::EndArray,
+                        JsonToken::Done];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn array_of_arrays() {
+        let text = r#"[[1,2],[3],[],[[[]]]]"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        let expected = [JsonToken::StartArray,
+                        JsonToken::StartArray,
+                        JsonToken::Integer(1),
+                        JsonToken::Integer(2),
+                        JsonToken::EndArray,
+                        JsonToken::StartArray,
+                        JsonToken::Integer(3),
+                        JsonToken::EndArray,
+                        JsonToken::StartArray,
+                        JsonToken::EndArray,
+                        JsonToken::StartArray,
+                        JsonToken::StartArray,
+                        JsonToken::StartArray,
+                        JsonToken::EndArray,
+                        JsonToken::EndArray,
+                        JsonToken::EndArray,
+                        JsonToken::EndArray,
+                        JsonToken::Done];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn sensor_things_list_response() {
+        let text = r#"{
+  "@iot.count": 2,
+  "value": [
+    {
+      "@iot.id": "1",
+      "name": "air_quality_readings",
+      "unitOfMeasurement": {
+        "symbol": "μg/m³"
+      },
+      "observedArea": null
+    },
+    {
+      "@iot.id": "2",
+      "phenomenonTime": ["2016-12-01T01:41:27Z", "2016-12-01T02:41:27Z"],
+      "result": [12.5, [true, false], {"quality": "good"}]
+    }
+  ]
+}"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        let expected = [JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("@iot.count")),
+                        JsonToken::Integer(2),
+                        JsonToken::PropertyName(j!("value")),
+                        JsonToken::StartArray,
+                        JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("@iot.id")),
+                        JsonToken::String(j!("1")),
+                        JsonToken::PropertyName(j!("name")),
+                        JsonToken::String(j!("air_quality_readings")),
+                        JsonToken::PropertyName(j!("unitOfMeasurement")),
+                        JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("symbol")),
+                        JsonToken::String(j!(r"μg/m³")),
+                        JsonToken::EndObject,
+                        JsonToken::PropertyName(j!("observedArea")),
+                        JsonToken::Null,
+                        JsonToken::EndObject,
+                        JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("@iot.id")),
+                        JsonToken::String(j!("2")),
+                        JsonToken::PropertyName(j!("phenomenonTime")),
+                        JsonToken::StartArray,
+                        JsonToken::String(j!("2016-12-01T01:41:27Z")),
+                        JsonToken::String(j!("2016-12-01T02:41:27Z")),
+                        JsonToken::EndArray,
+                        JsonToken::PropertyName(j!("result")),
+                        JsonToken::StartArray,
+                        JsonToken::Number(12.5),
+                        JsonToken::StartArray,
+                        JsonToken::True,
+                        JsonToken::False,
+                        JsonToken::EndArray,
+                        JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("quality")),
+                        JsonToken::String(j!("good")),
+                        JsonToken::EndObject,
+                        JsonToken::EndArray,
+                        JsonToken::EndObject,
+                        JsonToken::EndArray,
+                        JsonToken::EndObject,
+                        JsonToken::Done];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn mismatched_nesting() {
+        let cases = ["[1}", r#"{"a":1]"#, "[[1]}", r#"[{"a":[}]"#, "[1x]"];
+
+        for text in cases.iter() {
+            assert_eq!(first_error(JsonTokenizer::new(text)).kind,
+                       JsonErrorKind::UnexpectedCharacter);
+        }
+    }
+
+    #[test]
+    fn nesting_too_deep() {
+        let mut text = String::new();
+        for _ in 0..MAX_DEPTH + 1 {
+            text.push('[');
+        }
+        let mut tokenizer = JsonTokenizer::new(&text);
+        for _ in 0..MAX_DEPTH {
+            assert_eq!(tokenizer.next_token().unwrap(), JsonToken::StartArray);
+        }
+        assert_eq!(tokenizer.next_token().unwrap_err().kind,
+                   JsonErrorKind::NestingTooDeep);
+    }
+
+    #[test]
+    fn error_position() {
+        let text = "{\n  \"a\": 1,\n  \"b\": tru\n}";
+        let mut tokenizer = JsonTokenizer::new(&text);
+
+        for _ in 0..4 {
+            tokenizer.next_token().unwrap();
+        }
+        let error = tokenizer.next_token().unwrap_err();
+        assert_eq!(error,
+                   JsonError {
+                       kind: JsonErrorKind::UnexpectedCharacter,
+                       offset: 22,
+                       line: 3,
+                       column: 11,
+                       found: Some(b'\n'),
+                       expected: Some(Expected::Keyword("true")),
+                   });
+        assert_eq!(error.to_string(),
+                   "unexpected character at line 3, column 11 (offset 22): found byte 0x0a, \
+                    expected `true`");
+    }
+
+    #[test]
+    fn error_messages() {
+        let cases = [(r#"{"a":1 "b":2}"#,
+                      "unexpected character at line 1, column 8 (offset 7): found '\"', \
+                       expected `,` or `}`"),
+                     (r#"["abc"#,
+                      "unexpected end of input at line 1, column 6 (offset 5), expected `\"`"),
+                     (r#"["\u12x4"]"#,
+                      "invalid escape sequence at line 1, column 7 (offset 6): found 'x', \
+                       expected a hexadecimal digit"),
+                     (r#"[1, 2.e3]"#,
+                      "invalid number at line 1, column 7 (offset 6): found 'e', \
+                       expected a digit"),
+                     (r#"{1:2}"#,
+                      "unexpected character at line 1, column 2 (offset 1): found '1', \
+                       expected a property name")];
+
+        for &(text, message) in cases.iter() {
+            assert_eq!(first_error(JsonTokenizer::new(text)).to_string(), message);
+        }
+    }
+
+    #[test]
+    fn lenient_by_default() {
+        let cases = [r#"{"a" xyz : 1}"#, r#"{"a":1,}"#, "[1,]", "[1] [2]", "[\"a\tb\"]"];
+
+        for text in cases.iter() {
+            let mut tokenizer = JsonTokenizer::new(text);
+            loop {
+                match tokenizer.next_token().unwrap() {
+                    JsonToken::Done => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strict_mode() {
+        let cases = [(r#"{"a" xyz : 1}"#, JsonErrorKind::UnexpectedCharacter),
+                     (r#"{"a" 1}"#, JsonErrorKind::UnexpectedCharacter),
+                     (r#"{"a""#, JsonErrorKind::UnexpecteEof),
+                     (r#"{"a" : tru e}"#, JsonErrorKind::UnexpectedCharacter),
+                     (r#"{"a":1,}"#, JsonErrorKind::TrailingComma),
+                     ("[1,]", JsonErrorKind::TrailingComma),
+                     ("[[],]", JsonErrorKind::TrailingComma),
+                     ("[1] [2]", JsonErrorKind::TrailingContent),
+                     ("{} x", JsonErrorKind::TrailingContent),
+                     ("[\"a\tb\"]", JsonErrorKind::ControlCharacter),
+                     ("[\"a\u{0}\"]", JsonErrorKind::ControlCharacter)];
+
+        for &(text, ref kind) in cases.iter() {
+            assert_eq!(first_error(JsonTokenizer::new(text).strict(true)).kind, *kind);
+        }
+    }
+
+    #[test]
+    fn strict_mode_accepts_valid_documents() {
+        let text = "{\"a\" : [1, -2.5e3, {}, []],\r\n\t\"b\": {\"c\": \"\\t\"}} \n";
+        let mut tokenizer = JsonTokenizer::new(&text).strict(true);
+
+        let expected = [JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("a")),
+                        JsonToken::StartArray,
+                        JsonToken::Integer(1),
+                        JsonToken::Number(-2500.0),
+                        JsonToken::StartObject,
+                        JsonToken::EndObject,
+                        JsonToken::StartArray,
+                        JsonToken::EndArray,
+                        JsonToken::EndArray,
+                        JsonToken::PropertyName(j!("b")),
+                        JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("c")),
+                        JsonToken::String(j!("\\t")),
+                        JsonToken::EndObject,
+                        JsonToken::EndObject,
+                        JsonToken::Done,
+                        JsonToken::Done];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn relaxed_mode() {
+        let text = "// Device configuration\n{\n  ssid: \"home\", /* 2.4 GHz */\n  \
+                    retries: 3, // per hour\n  $ports: [80, 443,],\n  \"dns_1\": null,\n}\n// end";
+        let expected = [JsonToken::StartObject,
+                        JsonToken::PropertyName(j!("ssid")),
+                        JsonToken::String(j!("home")),
+                        JsonToken::PropertyName(j!("retries")),
+                        JsonToken::Integer(3),
+                        JsonToken::PropertyName(j!("$ports")),
+                        JsonToken::StartArray,
+                        JsonToken::Integer(80),
+                        JsonToken::Integer(443),
+                        JsonToken::EndArray,
+                        JsonToken::PropertyName(j!("dns_1")),
+                        JsonToken::Null,
+                        JsonToken::EndObject,
+                        JsonToken::Done];
+
+        // Strict about everything else.
+        let mut tokenizer = JsonTokenizer::new(text).strict(true).relaxed(true);
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
+        }
+
+        let owned = expected[..expected.len() - 1].iter().map(|token| token.into_owned());
+        let owned = owned.collect::<Vec<_>>();
+        for chunk_size in [1, 3, 7, 1024].iter() {
+            let mut buffer = [0u8; 32];
+            let mut tokenizer = JsonStreamTokenizer::new(&mut buffer).relaxed(true);
+            let mut tokens = Vec::new();
+            let mut input = text.as_bytes();
+            loop {
+                let fed = tokenizer.feed(&input[..cmp::min(*chunk_size, input.len())]);
+                input = &input[fed..];
+                if input.is_empty() {
+                    tokenizer.finish();
+                }
+                match tokenizer.next_token().unwrap() {
+                    Some(JsonToken::Done) => break,
+                    Some(token) => tokens.push(token.into_owned()),
+                    None => {}
+                }
+            }
+            assert_eq!(tokens, owned);
+        }
+    }
+
+    #[test]
+    fn relaxed_mode_errors() {
+        let cases = [("{a: 1 /* x }", JsonErrorKind::UnexpecteEof, 6),
+                     ("[1, / 2]", JsonErrorKind::UnexpectedCharacter, 4),
+                     ("{1a: 2}", JsonErrorKind::UnexpectedCharacter, 1),
+                     ("{a b: 2}", JsonErrorKind::UnexpectedCharacter, 3),
+                     ("{a: b}", JsonErrorKind::UnexpectedCharacter, 4),
+                     ("[1] // x\n2", JsonErrorKind::TrailingContent, 9)];
+
+        for &(text, ref kind, offset) in cases.iter() {
+            let error = first_error(JsonTokenizer::new(text).strict(true).relaxed(true));
+            assert_eq!((error.kind, error.offset), (*kind, offset));
+        }
+
+        // Not without asking for it.
+        let error = first_error(JsonTokenizer::new("{a: 1}").strict(true));
+        assert_eq!(error.kind, JsonErrorKind::UnexpectedCharacter);
+        let error = first_error(JsonTokenizer::new("[1 // x\n]"));
+        assert_eq!(error.kind, JsonErrorKind::UnexpectedCharacter);
+    }
+
+    #[test]
+    fn raw_numbers() {
+        let text = "[1, -2.5e3, 0.0125, 18446744073709551616]";
+        let tokens = JsonTokenizer::new(text).raw_numbers(true).collect::<Result<Vec<_>, _>>();
+        assert_eq!(tokens.unwrap(),
+                   [JsonToken::StartArray,
+                    JsonToken::Integer(1),
+                    JsonToken::RawNumber("-2.5e3"),
+                    JsonToken::RawNumber("0.0125"),
+                    JsonToken::RawNumber("18446744073709551616"),
+                    JsonToken::EndArray]);
+        assert_eq!(first_error(JsonTokenizer::new(text)).kind, JsonErrorKind::NumberOverflow);
+    }
+
+    #[test]
+    fn iterator() {
+        let tokens = JsonTokenizer::new(r#"{"a": [1, true]}"#).collect::<Result<Vec<_>, _>>();
+        assert_eq!(tokens.unwrap(),
+                   [JsonToken::StartObject,
+                    JsonToken::PropertyName(j!("a")),
+                    JsonToken::StartArray,
+                    JsonToken::Integer(1),
+                    JsonToken::True,
+                    JsonToken::EndArray,
+                    JsonToken::EndObject]);
+
+        // Iterating stops after the first error.
+        let mut tokenizer = JsonTokenizer::new("[1, x, 2]");
+        assert_eq!(tokenizer.next(), Some(Ok(JsonToken::StartArray)));
+        assert_eq!(tokenizer.next(), Some(Ok(JsonToken::Integer(1))));
+        assert_eq!(tokenizer.next().unwrap().unwrap_err().kind,
+                   JsonErrorKind::UnexpectedCharacter);
+        assert_eq!(tokenizer.next(), None);
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn peek_and_skip() {
+        let text = r#"{"skip": {"a": [1, {}]}, "keep": 2, "list": [[3], 4, 5], "end": {}}"#;
+        let mut tokenizer = JsonTokenizer::new(text);
+
+        assert_eq!(tokenizer.peek_token(), Ok(JsonToken::StartObject));
+        assert_eq!(tokenizer.peek_token(), Ok(JsonToken::StartObject));
+        assert_eq!(tokenizer.token_offset(), 0);
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::StartObject));
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::PropertyName(j!("skip"))));
+        tokenizer.skip_value().unwrap();
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::PropertyName(j!("keep"))));
+        assert_eq!(tokenizer.peek_token(), Ok(JsonToken::Integer(2)));
+        tokenizer.skip_value().unwrap();
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::PropertyName(j!("list"))));
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::StartArray));
+        tokenizer.skip_value().unwrap();
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::Integer(4)));
+        // There's nothing left to skip before the end of the array.
+        tokenizer.skip_value().unwrap();
+        tokenizer.skip_value().unwrap();
+        assert_eq!(tokenizer.peek_token(), Ok(JsonToken::EndArray));
+        tokenizer.skip_value().unwrap();
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::EndArray));
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::PropertyName(j!("end"))));
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::StartObject));
+        tokenizer.skip_value().unwrap();
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::EndObject));
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::EndObject));
+        assert_eq!(tokenizer.next_token(), Ok(JsonToken::Done));
+        tokenizer.skip_value().unwrap();
+
+        // The same things are skipped with or without peeking first.
+        for &peek in [false, true].iter() {
+            let mut tokenizer = JsonTokenizer::new(r#"[[1, [2]], {"a": 1, "b": {"c": []}}, 3]"#);
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::StartArray));
+            if peek {
+                assert_eq!(tokenizer.peek_token(), Ok(JsonToken::StartArray));
+            }
+            tokenizer.skip_value().unwrap();
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::StartObject));
+            if peek {
+                assert_eq!(tokenizer.peek_token(), Ok(JsonToken::PropertyName(j!("a"))));
+            }
+            // A property name is skipped with its value.
+            tokenizer.skip_value().unwrap();
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::PropertyName(j!("b"))));
+            if peek {
+                assert_eq!(tokenizer.peek_token(), Ok(JsonToken::StartObject));
+            }
+            tokenizer.skip_value().unwrap();
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::EndObject));
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::Integer(3)));
+
+            // Errors are peeked too.
+            let mut tokenizer = JsonTokenizer::new("[1, {]");
+            tokenizer.next_token().unwrap();
+            if peek {
+                assert_eq!(tokenizer.peek_token(), Ok(JsonToken::Integer(1)));
+            }
+            tokenizer.skip_value().unwrap();
+            assert_eq!(tokenizer.skip_value().unwrap_err().kind,
+                       JsonErrorKind::UnexpectedCharacter);
+        }
+    }
+
+    #[test]
+    fn skip_container() {
+        for &peek in [false, true].iter() {
+            let text = r#"{"a": [1, [2], {"b": 3}, 4], "c": {"d": 5, "e": []}, "f": 6}"#;
+            let mut tokenizer = JsonTokenizer::new(text);
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::StartObject));
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::PropertyName(j!("a"))));
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::StartArray));
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::Integer(1)));
+            if peek {
+                assert_eq!(tokenizer.peek_token(), Ok(JsonToken::StartArray));
+            }
+            tokenizer.skip_container().unwrap();
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::PropertyName(j!("c"))));
+            // Right after it starts, the whole object is skipped.
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::StartObject));
+            if peek {
+                assert_eq!(tokenizer.peek_token(), Ok(JsonToken::PropertyName(j!("d"))));
+            }
+            tokenizer.skip_container().unwrap();
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::PropertyName(j!("f"))));
+            if peek {
+                assert_eq!(tokenizer.peek_token(), Ok(JsonToken::Integer(6)));
+            }
+            tokenizer.skip_container().unwrap();
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::Done));
+
+            // Before the root value, the whole document is skipped.
+            let mut tokenizer = JsonTokenizer::new("[1, [2]]");
+            if peek {
+                assert_eq!(tokenizer.peek_token(), Ok(JsonToken::StartArray));
+            }
+            tokenizer.skip_container().unwrap();
+            assert_eq!(tokenizer.next_token(), Ok(JsonToken::Done));
+
+            let mut tokenizer = JsonTokenizer::new("[[1, {]]");
+            tokenizer.next_token().unwrap();
+            tokenizer.next_token().unwrap();
+            if peek {
+                assert_eq!(tokenizer.peek_token(), Ok(JsonToken::Integer(1)));
+            }
+            assert_eq!(tokenizer.skip_container().unwrap_err().kind,
+                       JsonErrorKind::UnexpectedCharacter);
+        }
+    }
+
+    #[test]
+    fn stream_in_chunks() {
+        let text = r#"{"id": "Datastreams(1)", "count": 1480556487, "values": [-12.5e-1, true,
+                       false, null, {"symbol": "μg/m³"}, [], "😀"]}"#;
+        let mut tokenizer = JsonTokenizer::new(&text);
+        let mut expected = Vec::new();
+        loop {
+            match tokenizer.next_token().unwrap() {
+                JsonToken::Done => break,
+                token => expected.push(token.into_owned()),
+            }
+        }
+
+        for chunk_size in [1, 2, 3, 5, 7, 16, 1024].iter() {
+            let mut buffer = [0u8; 32];
+            assert_eq!(stream_tokens(text, *chunk_size, &mut buffer).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn stream_split_number() {
+        let mut buffer = [0u8; 16];
+        let mut tokenizer = JsonStreamTokenizer::new(&mut buffer);
+
+        tokenizer.feed(b"[12");
+        assert_eq!(tokenizer.next_token().unwrap(), Some(JsonToken::StartArray));
+        assert_eq!(tokenizer.next_token().unwrap(), None);
+        tokenizer.feed(b"34");
+        assert_eq!(tokenizer.next_token().unwrap(), None);
+        tokenizer.feed(b"]");
+        assert_eq!(tokenizer.next_token().unwrap(), Some(JsonToken::Integer(1234)));
+        assert_eq!(tokenizer.next_token().unwrap(), Some(JsonToken::EndArray));
+        assert_eq!(tokenizer.next_token().unwrap(), Some(JsonToken::Done));
+    }
+
+    #[test]
+    fn stream_long_whitespace() {
+        // Whitespace doesn't have to fit in the buffer with the next token.
+        let text = format!("[1,{:40}2, {{\n{:30}\"a\"\n{:30}: 3}}\n{:20}]", "", "", "", "");
+        let mut buffer = [0u8; 16];
+        assert_eq!(stream_tokens(&text, 8, &mut buffer).unwrap(),
+                   [OwnedJsonToken::StartArray,
+                    OwnedJsonToken::Integer(1),
+                    OwnedJsonToken::Integer(2),
+                    OwnedJsonToken::StartObject,
+                    OwnedJsonToken::PropertyName(s!("a")),
+                    OwnedJsonToken::Integer(3),
+                    OwnedJsonToken::EndObject,
+                    OwnedJsonToken::EndArray]);
+
+        // Nor do comments in relaxed mode, as long as each one fits.
+        let text = "[1, // one\n    // two\n    /* three */\n    2]";
+        let mut buffer = [0u8; 16];
+        let mut tokenizer = JsonStreamTokenizer::new(&mut buffer).relaxed(true);
+        let mut tokens = Vec::new();
+        for chunk in text.as_bytes().chunks(4) {
+            assert_eq!(tokenizer.feed(chunk), chunk.len());
+            while let Some(token) = tokenizer.next_token().unwrap() {
+                if token == JsonToken::Done {
+                    break;
+                }
+                tokens.push(token.into_owned());
+            }
+        }
+        assert_eq!(tokens,
+                   [OwnedJsonToken::StartArray,
+                    OwnedJsonToken::Integer(1),
+                    OwnedJsonToken::Integer(2),
+                    OwnedJsonToken::EndArray]);
+    }
+
+    #[test]
+    fn stream_errors() {
+        // The longest token doesn't fit in the buffer.
+        let mut buffer = [0u8; 8];
+        assert_eq!(stream_tokens(r#"["0123456789"]"#, 4, &mut buffer).unwrap_err().kind,
+                   JsonErrorKind::TokenTooLong);
+
+        // Errors are located in the whole input.
+        let mut buffer = [0u8; 8];
+        let error = stream_tokens("[1,\n 2,\n 3,\n 4,\n x]", 3, &mut buffer).unwrap_err();
+        assert_eq!((error.kind, error.offset, error.line, error.column),
+                   (JsonErrorKind::UnexpectedCharacter, 17, 5, 2));
+
+        // The input ends in the middle of the document.
+        let mut buffer = [0u8; 8];
+        assert_eq!(stream_tokens("[1, 2", 2, &mut buffer).unwrap_err().kind,
+                   JsonErrorKind::UnexpecteEof);
+    }
+
+    // Streams `text` with string fragments, joining the fragments of each
+    // string in a single `String` token.
+    fn stream_fragments(text: &str,
+                        chunk_size: usize,
+                        limits: JsonLimits)
+                        -> Result<Vec<OwnedJsonToken>, JsonError> {
+        let mut buffer = [0u8; 16];
+        let mut tokenizer = JsonStreamTokenizer::new(&mut buffer)
+            .string_fragments(true)
+            .limits(limits);
+        let mut tokens = Vec::new();
+        let mut fragments = None;
+        let mut input = text.as_bytes();
+        loop {
+            let fed = tokenizer.feed(&input[..cmp::min(chunk_size, input.len())]);
+            input = &input[fed..];
+            if input.is_empty() {
+                tokenizer.finish();
+            }
+            while let Some(token) = tokenizer.next_token()? {
+                match token {
+                    JsonToken::Done => return Ok(tokens),
+                    JsonToken::StartString => fragments = Some(String::new()),
+                    JsonToken::StringFragment(fragment) => {
+                        fragments.as_mut().unwrap().push_str(&fragment.to_string())
+                    }
+                    JsonToken::EndString => {
+                        tokens.push(OwnedJsonToken::String(fragments.take().unwrap()))
+                    }
+                    token => tokens.push(token.into_owned()),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stream_string_fragments() {
+        // Escapes and multibyte characters are never split between fragments.
+        let text = r#"["short", "a long string with \"escapes\" é😀 and μg/m³ 😀 in it",
+                      {"key": "0123456789abcdefghijklmnopqrstuvwxyz"}]"#;
+        let expected = [OwnedJsonToken::StartArray,
+                        OwnedJsonToken::String(s!("short")),
+                        OwnedJsonToken::String(s!("a long string with \"escapes\" é😀 and μg/m³ \
+                                                   😀 in it")),
+                        OwnedJsonToken::StartObject,
+                        OwnedJsonToken::PropertyName(s!("key")),
+                        OwnedJsonToken::String(s!("0123456789abcdefghijklmnopqrstuvwxyz")),
+                        OwnedJsonToken::EndObject,
+                        OwnedJsonToken::EndArray];
+        for chunk_size in [1, 2, 3, 5, 7, 16].iter() {
+            assert_eq!(stream_fragments(text, *chunk_size, JsonLimits::default()).unwrap(),
+                       expected);
+        }
+
+        // The whole string is checked against the limits.
+        let limits = JsonLimits { max_string_len: 30, ..JsonLimits::default() };
+        assert_eq!(stream_fragments(r#"["0123456789abcdefghijklmnopqrstuvwxyz"]"#, 4, limits)
+                       .unwrap_err()
+                       .kind,
+                   JsonErrorKind::StringTooLong);
+
+        // Property names still have to fit in the buffer.
+        assert_eq!(stream_fragments(r#"{"0123456789abcdefghij": 1}"#, 4, JsonLimits::default())
+                       .unwrap_err()
+                       .kind,
+                   JsonErrorKind::TokenTooLong);
+    }
+
+    #[test]
+    fn limits() {
+        let limits = JsonLimits {
+            max_depth: 2,
+            max_string_len: 4,
+            max_number_len: 3,
+            max_tokens: 6,
+        };
+        let tokenize = |text| JsonTokenizer::new(text).limits(limits);
+
+        // Right at the limits.
+        let mut tokenizer = tokenize(r#"{"abcd": [-12]}"#);
+        for _ in 0..6 {
+            tokenizer.next_token().unwrap();
+        }
+        assert_eq!(tokenizer.next_token().unwrap(), JsonToken::Done);
+
+        let error = first_error(tokenize(r#"[[[1]]]"#));
+        assert_eq!((error.kind, error.offset), (JsonErrorKind::NestingTooDeep, 2));
+        let error = first_error(tokenize(r#"{"abcde": 1}"#));
+        assert_eq!((error.kind, error.offset), (JsonErrorKind::StringTooLong, 1));
+        let error = first_error(tokenize(r#"["abc\u0041"]"#));
+        assert_eq!((error.kind, error.offset), (JsonErrorKind::StringTooLong, 1));
+        let error = first_error(tokenize(r#"[1.25]"#));
+        assert_eq!((error.kind, error.offset), (JsonErrorKind::NumberTooLong, 1));
+        let error = first_error(tokenize(r#"[1, 2, 3, 4, 5, 6]"#));
+        assert_eq!((error.kind, error.offset), (JsonErrorKind::TooManyTokens, 16));
+
+        // The token count carries over chunks.
+        let mut buffer = [0u8; 16];
+        let mut tokenizer = JsonStreamTokenizer::new(&mut buffer).limits(limits);
+        tokenizer.feed(b"[1, 2, 3");
+        for _ in 0..3 {
+            tokenizer.next_token().unwrap();
+        }
+        tokenizer.feed(b", 4, 5, 6]");
+        tokenizer.finish();
+        let mut error = None;
+        for _ in 0..4 {
+            if let Err(err) = tokenizer.next_token() {
+                error = Some(err);
+                break;
+            }
+        }
+        assert_eq!(error.map(|err| err.kind), Some(JsonErrorKind::TooManyTokens));
+    }
 }
@@ -0,0 +1,333 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// JSON Lines, also known as newline delimited JSON: one document per line.
+// Each line is tokenized on its own, so a corrupt record only affects its
+// own line. Input that doesn't fit in RAM can be read chunk by chunk with
+// `JsonStreamTokenizer::lines()`.
+
+use core::fmt::Write;
+use decode::{decode_with, DecodeError, DecodeErrorKind, FromJson};
+use json::{JsonError, JsonLimits, JsonTokenizer};
+use writer::{JsonWriteError, JsonWriter};
+
+/// Iterates over the non-blank lines of a JSON Lines input.
+pub struct JsonLines<'a> {
+    input: &'a str,
+    pos: usize,
+    line: usize,
+    strict: bool,
+    limits: JsonLimits,
+}
+
+impl<'a> JsonLines<'a> {
+    pub fn new(input: &'a str) -> Self {
+        JsonLines {
+            input: input,
+            pos: 0,
+            line: 0,
+            strict: false,
+            limits: JsonLimits::default(),
+        }
+    }
+
+    /// See `JsonTokenizer::strict()`. In strict mode, a line holding more
+    /// than one document is an error.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// See `JsonTokenizer::limits()`, which apply to each line.
+    pub fn limits(mut self, limits: JsonLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+impl<'a> Iterator for JsonLines<'a> {
+    type Item = JsonLine<'a>;
+
+    fn next(&mut self) -> Option<JsonLine<'a>> {
+        while self.pos < self.input.len() {
+            let rest = &self.input[self.pos..];
+            let len = rest.find('\n').unwrap_or(rest.len());
+            let line = JsonLine {
+                number: self.line + 1,
+                offset: self.pos,
+                text: &rest[..len],
+                strict: self.strict,
+                limits: self.limits,
+            };
+            self.pos += len + 1;
+            self.line += 1;
+            if !line.text.bytes().all(|c| c == b' ' || c == b'\t' || c == b'\r') {
+                return Some(line);
+            }
+        }
+        None
+    }
+}
+
+/// One record of a JSON Lines input.
+#[derive(Clone, Copy, Debug)]
+pub struct JsonLine<'a> {
+    /// Line number in the input, starting at 1.
+    pub number: usize,
+    /// Byte offset of the line in the input.
+    pub offset: usize,
+    /// The line, without the `\n`.
+    pub text: &'a str,
+    strict: bool,
+    limits: JsonLimits,
+}
+
+impl<'a> JsonLine<'a> {
+    /// A tokenizer for the document on this line.
+    pub fn tokenizer(&self) -> JsonTokenizer<'a> {
+        JsonTokenizer::new(self.text).strict(self.strict).limits(self.limits)
+    }
+
+    /// Turns an error from `tokenizer()` into one located in the whole input.
+    pub fn locate(&self, err: JsonError) -> JsonError {
+        let mut err = err;
+        err.offset += self.offset;
+        err.line = self.number;
+        err
+    }
+
+    /// Decodes the document on this line, see `decode()`.
+    pub fn decode<T: FromJson>(&self) -> Result<T, DecodeError> {
+        decode_with(&mut self.tokenizer()).map_err(|err| {
+            let mut err = err;
+            if let DecodeErrorKind::Json(json) = err.kind {
+                err.kind = DecodeErrorKind::Json(self.locate(json));
+            }
+            err
+        })
+    }
+}
+
+/// Writes JSON Lines, each record being a complete document on its own line.
+pub struct JsonLinesWriter<W: Write> {
+    out: W,
+    records: usize,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(out: W) -> Self {
+        JsonLinesWriter {
+            out: out,
+            records: 0,
+        }
+    }
+
+    /// Number of records written successfully.
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Writes one record with `write`, which has to write a complete
+    /// document. If it fails the line is still terminated, so that the
+    /// partial record doesn't corrupt the next one.
+    pub fn write_line<F>(&mut self, write: F) -> Result<(), JsonWriteError>
+        where F: FnOnce(&mut JsonWriter<&mut W>) -> Result<(), JsonWriteError>
+    {
+        let result = {
+            let mut writer = JsonWriter::new(&mut self.out);
+            match write(&mut writer) {
+                Ok(()) => writer.finish().map(|_| ()),
+                Err(err) => Err(err),
+            }
+        };
+        self.out.write_char('\n')?;
+        if result.is_ok() {
+            self.records += 1;
+        }
+        result
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use collections::String;
+    use collections::vec::Vec;
+    use core::cmp;
+    use decode::DecodeErrorKind;
+    use json::{JsonError, JsonErrorKind, JsonStreamTokenizer, JsonToken, OwnedJsonToken};
+    use lines::{JsonLines, JsonLinesWriter};
+    use writer::{JsonWriteError, JsonWriter, SliceWriter};
+
+    #[derive(Debug, PartialEq)]
+    struct Observation {
+        time: i64,
+        result: f64,
+    }
+
+    impl_from_json!(Observation {
+        time: "t",
+        result: "r",
+    });
+
+    static BATCH: &'static str = "{\"t\": 1480556487, \"r\": 12.5}\r\n\
+                                  \n\
+                                  {\"t\": 1480556488, \"r\": \x0012.7}\n\
+                                  {\"t\": 1480556489, \"r\": 13.0}\n\
+                                  {\"t\": 1480556490,\n\
+                                  \"r\": 13.1}\n\
+                                  [] []\n\
+                                  \t \n";
+
+    #[test]
+    fn read_records() {
+        let lines = JsonLines::new(BATCH).collect::<Vec<_>>();
+        assert_eq!(lines.iter().map(|line| line.number).collect::<Vec<_>>(),
+                   vec![1, 3, 4, 5, 6, 7]);
+
+        let results = lines.iter().map(|line| line.decode::<Observation>()).collect::<Vec<_>>();
+        assert_eq!(results[0],
+                   Ok(Observation {
+                       time: 1480556487,
+                       result: 12.5,
+                   }));
+        assert_eq!(results[2],
+                   Ok(Observation {
+                       time: 1480556489,
+                       result: 13.0,
+                   }));
+
+        // The corrupt records are reported on their own line.
+        match results[1] {
+            Err(ref err) => {
+                match err.kind {
+                    DecodeErrorKind::Json(err) => {
+                        assert_eq!((err.kind, err.line, err.column),
+                                   (JsonErrorKind::UnexpectedCharacter, 3, 24));
+                        assert_eq!(BATCH.as_bytes()[err.offset], 0);
+                    }
+                    _ => panic!("Expected a JSON error"),
+                }
+            }
+            _ => panic!("Expected an error"),
+        }
+        assert!(results[3].is_err());
+        assert!(results[4].is_err());
+
+        // Each line is a document of its own.
+        let mut tokenizer = lines[5].tokenizer();
+        assert_eq!(tokenizer.next_token().unwrap(), JsonToken::StartArray);
+        assert_eq!(tokenizer.next_token().unwrap(), JsonToken::EndArray);
+        assert_eq!(tokenizer.next_token().unwrap(), JsonToken::Done);
+        let mut tokenizer = JsonLines::new(BATCH).strict(true).last().unwrap().tokenizer();
+        tokenizer.next_token().unwrap();
+        tokenizer.next_token().unwrap();
+        assert_eq!(tokenizer.next_token().unwrap_err().kind, JsonErrorKind::TrailingContent);
+    }
+
+    // Reads the records of `BATCH` a few bytes at a time.
+    fn stream_records(strict: bool) -> Vec<Result<Vec<OwnedJsonToken>, JsonError>> {
+        let mut buffer = [0u8; 16];
+        let mut tokenizer = JsonStreamTokenizer::new(&mut buffer).strict(strict).lines(true);
+        let mut records = Vec::new();
+        let mut tokens = Vec::new();
+        let mut input = BATCH.as_bytes();
+        loop {
+            match tokenizer.next_token() {
+                Ok(Some(JsonToken::Done)) => {
+                    records.push(Ok(tokens));
+                    tokens = Vec::new();
+                }
+                Ok(Some(token)) => tokens.push(token.into_owned()),
+                Ok(None) if input.is_empty() => return records,
+                Ok(None) => {
+                    let fed = tokenizer.feed(&input[..cmp::min(5, input.len())]);
+                    input = &input[fed..];
+                    if input.is_empty() {
+                        tokenizer.finish();
+                    }
+                }
+                Err(err) => {
+                    records.push(Err(err));
+                    tokens = Vec::new();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stream_records_in_chunks() {
+        let records = stream_records(false);
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[0],
+                   Ok(vec![OwnedJsonToken::StartObject,
+                           OwnedJsonToken::PropertyName("t".into()),
+                           OwnedJsonToken::Integer(1480556487),
+                           OwnedJsonToken::PropertyName("r".into()),
+                           OwnedJsonToken::Number(12.5),
+                           OwnedJsonToken::EndObject]));
+        assert_eq!(records[2], records[0].clone().map(|mut tokens| {
+            tokens[2] = OwnedJsonToken::Integer(1480556489);
+            tokens[4] = OwnedJsonToken::Number(13.0);
+            tokens
+        }));
+
+        // Errors are located in the whole input, and only affect their line.
+        let err = records[1].clone().unwrap_err();
+        assert_eq!((err.kind, err.line, err.column),
+                   (JsonErrorKind::UnexpectedCharacter, 3, 24));
+        assert_eq!(BATCH.as_bytes()[err.offset], 0);
+        let err = records[3].clone().unwrap_err();
+        assert_eq!((err.kind, err.line), (JsonErrorKind::UnexpecteEof, 5));
+        assert_eq!(records[4], Ok(vec![OwnedJsonToken::String("r".into())]));
+        assert_eq!(records[5],
+                   Ok(vec![OwnedJsonToken::StartArray, OwnedJsonToken::EndArray]));
+
+        let records = stream_records(true);
+        assert_eq!(records.len(), 6);
+        assert!(records[0].is_ok());
+        assert_eq!(records[4].clone().unwrap_err().kind, JsonErrorKind::TrailingContent);
+        let err = records[5].clone().unwrap_err();
+        assert_eq!((err.kind, err.line, err.column), (JsonErrorKind::TrailingContent, 7, 4));
+    }
+
+    fn observation(writer: &mut JsonWriter<&mut SliceWriter>,
+                   time: i64,
+                   result: f64)
+                   -> Result<(), JsonWriteError> {
+        writer.begin_object()?;
+        writer.key("t")?;
+        writer.integer(time)?;
+        writer.key("r")?;
+        writer.float(result)?;
+        writer.end_object()
+    }
+
+    #[test]
+    fn write_records() {
+        let mut buffer = [0u8; 128];
+        let mut lines = JsonLinesWriter::new(SliceWriter::new(&mut buffer));
+        lines.write_line(|writer| observation(writer, 1480556487, 12.5)).unwrap();
+        assert_eq!(lines.write_line(|writer| writer.begin_array()),
+                   Err(JsonWriteError::Incomplete));
+        lines.write_line(|writer| observation(writer, 1480556488, 12.0)).unwrap();
+        assert_eq!(lines.records(), 2);
+        let out = lines.into_inner();
+        assert_eq!(out.as_str(),
+                   "{\"t\":1480556487,\"r\":12.5}\n[\n{\"t\":1480556488,\"r\":12.0}\n");
+
+        let records = JsonLines::new(out.as_str())
+            .map(|line| line.decode::<Observation>().is_ok())
+            .collect::<Vec<_>>();
+        assert_eq!(records, vec![true, false, true]);
+
+        let mut lines = JsonLinesWriter::new(String::new());
+        lines.write_line(|writer| writer.string("a\nb")).unwrap();
+        assert_eq!(lines.into_inner(), "\"a\\nb\"\n");
+    }
+}
@@ -0,0 +1,305 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// RFC 7396 JSON Merge Patch, applied to document trees. The patched document
+// is written out instead of being built in place, so that the target can
+// stay in flash or in a read-only buffer until the result is persisted.
+
+use core::fmt::{self, Write};
+use dom::JsonValue;
+use json::JsonStr;
+use writer::{JsonWriteError, JsonWriter};
+
+/// How a member of the target is affected by a patch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergeChange {
+    Added,
+    Removed,
+    /// The value is different. An object that is patched member by member is
+    /// not replaced, its members are reported instead.
+    Replaced,
+}
+
+/// Location of a change, from the innermost member name to the root.
+#[derive(Clone, Copy, Debug)]
+pub struct MergePath<'p, 'a: 'p> {
+    key: Option<JsonStr<'a>>,
+    parent: Option<&'p MergePath<'p, 'a>>,
+}
+
+impl<'p, 'a> MergePath<'p, 'a> {
+    /// The name of the changed member, or `None` for the whole document.
+    pub fn key(&self) -> Option<JsonStr<'a>> {
+        self.key
+    }
+
+    /// The path of the object holding the member, if any.
+    pub fn parent(&self) -> Option<&'p MergePath<'p, 'a>> {
+        self.parent
+    }
+}
+
+/// Writes the path as a JSON Pointer, like `/sampling/interval`.
+impl<'p, 'a> fmt::Display for MergePath<'p, 'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(parent) = self.parent {
+            write!(f, "{}", parent)?;
+        }
+        if let Some(key) = self.key {
+            f.write_char('/')?;
+            for c in key.chars() {
+                match c {
+                    '~' => f.write_str("~0")?,
+                    '/' => f.write_str("~1")?,
+                    _ => f.write_char(c)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The value of the last member of an object named `key`, which is the one
+// that counts when names are repeated.
+fn member<'n, 'a>(object: JsonValue<'n, 'a>, key: JsonStr) -> Option<JsonValue<'n, 'a>> {
+    object.members().filter(|&(name, _)| name == key).last().map(|(_, value)| value)
+}
+
+fn merge<W: Write>(target: Option<JsonValue>,
+                   patch: JsonValue,
+                   writer: &mut JsonWriter<W>)
+                   -> Result<(), JsonWriteError> {
+    if !patch.is_object() {
+        return patch.write(writer);
+    }
+    writer.begin_object()?;
+    let target = target.and_then(|target| if target.is_object() { Some(target) } else { None });
+    if let Some(target) = target {
+        // The members of the target keep their order.
+        for (i, (key, value)) in target.members().enumerate() {
+            if target.members().skip(i + 1).any(|(name, _)| name == key) {
+                continue;
+            }
+            match member(patch, key) {
+                Some(update) if update.is_null() => {}
+                Some(update) => {
+                    writer.json_key(key)?;
+                    merge(Some(value), update, writer)?;
+                }
+                None => {
+                    writer.json_key(key)?;
+                    value.write(writer)?;
+                }
+            }
+        }
+    }
+    for (i, (key, update)) in patch.members().enumerate() {
+        let added = target.map_or(true, |target| member(target, key).is_none());
+        if !added || update.is_null() || patch.members().skip(i + 1).any(|(name, _)| name == key) {
+            continue;
+        }
+        writer.json_key(key)?;
+        merge(None, update, writer)?;
+    }
+    writer.end_object()
+}
+
+/// Applies `patch` to `target` and writes the result. `null` members of the
+/// patch remove members of the target, objects are merged recursively and
+/// anything else replaces the target value.
+pub fn merge_patch<W: Write>(target: JsonValue,
+                             patch: JsonValue,
+                             writer: &mut JsonWriter<W>)
+                             -> Result<(), JsonWriteError> {
+    merge(Some(target), patch, writer)
+}
+
+// Whether merging `patch` into nothing gives `value`.
+fn creates(patch: JsonValue, value: JsonValue) -> bool {
+    if !patch.is_object() {
+        return patch == value;
+    }
+    let members = patch.members().filter(|&(_, update)| !update.is_null()).count();
+    value.is_object() && value.len() == members &&
+    patch.members().all(|(key, update)| {
+        update.is_null() || member(value, key).map_or(false, |value| creates(update, value))
+    })
+}
+
+fn changes<'n, 'p, 'a, F>(target: JsonValue<'n, 'a>,
+                          patch: JsonValue<'n, 'a>,
+                          path: &'p MergePath<'p, 'a>,
+                          report: &mut F)
+                          -> usize
+    where F: FnMut(&MergePath, MergeChange)
+{
+    if !target.is_object() || !patch.is_object() {
+        let unchanged = if patch.is_object() {
+            creates(patch, target)
+        } else {
+            patch == target
+        };
+        if unchanged {
+            return 0;
+        }
+        report(path, MergeChange::Replaced);
+        return 1;
+    }
+    let mut count = 0;
+    for (i, (key, update)) in patch.members().enumerate() {
+        if patch.members().skip(i + 1).any(|(name, _)| name == key) {
+            continue;
+        }
+        let path = MergePath {
+            key: Some(key),
+            parent: Some(path),
+        };
+        match member(target, key) {
+            Some(_) if update.is_null() => {
+                report(&path, MergeChange::Removed);
+                count += 1;
+            }
+            Some(value) => count += changes(value, update, &path, report),
+            None if update.is_null() => {}
+            None => {
+                report(&path, MergeChange::Added);
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// The dry run of `merge_patch()`: reports the members that it would add,
+/// remove or replace, and returns how many there are. Nothing is reported
+/// for members that the patch leaves unchanged.
+pub fn merge_changes<'n, 'a, F>(target: JsonValue<'n, 'a>,
+                                patch: JsonValue<'n, 'a>,
+                                report: F)
+                                -> usize
+    where F: FnMut(&MergePath, MergeChange)
+{
+    let mut report = report;
+    let root = MergePath {
+        key: None,
+        parent: None,
+    };
+    changes(target, patch, &root, &mut report)
+}
+
+#[cfg(test)]
+mod test {
+
+    use collections::String;
+    use collections::string::ToString;
+    use collections::vec::Vec;
+    use dom::{JsonNode, JsonValue};
+    use json::JsonTokenizer;
+    use merge::{merge_changes, merge_patch, MergeChange};
+    use writer::JsonWriter;
+
+    fn patched(target: &str, patch: &str) -> String {
+        let mut target_nodes = [JsonNode::new(); 32];
+        let mut patch_nodes = [JsonNode::new(); 32];
+        let target = JsonValue::parse(&mut JsonTokenizer::new(target), &mut target_nodes).unwrap();
+        let patch = JsonValue::parse(&mut JsonTokenizer::new(patch), &mut patch_nodes).unwrap();
+        let mut writer = JsonWriter::new(String::new());
+        merge_patch(target, patch, &mut writer).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn changes(target: &str, patch: &str) -> Vec<(String, MergeChange)> {
+        let mut target_nodes = [JsonNode::new(); 32];
+        let mut patch_nodes = [JsonNode::new(); 32];
+        let target = JsonValue::parse(&mut JsonTokenizer::new(target), &mut target_nodes).unwrap();
+        let patch = JsonValue::parse(&mut JsonTokenizer::new(patch), &mut patch_nodes).unwrap();
+        let mut changes = Vec::new();
+        let count = merge_changes(target, patch, |path, change| {
+            changes.push((path.to_string(), change));
+        });
+        assert_eq!(count, changes.len());
+        changes
+    }
+
+    // Examples from RFC 7396 appendix A.
+    #[test]
+    fn rfc_examples() {
+        let cases = [(r#"{"a":"b"}"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+                     (r#"{"a":"b"}"#, r#"{"b":"c"}"#, r#"{"a":"b","b":"c"}"#),
+                     (r#"{"a":"b"}"#, r#"{"a":null}"#, r#"{}"#),
+                     (r#"{"a":"b","b":"c"}"#, r#"{"a":null}"#, r#"{"b":"c"}"#),
+                     (r#"{"a":["b"]}"#, r#"{"a":"c"}"#, r#"{"a":"c"}"#),
+                     (r#"{"a":"c"}"#, r#"{"a":["b"]}"#, r#"{"a":["b"]}"#),
+                     (r#"{"a":{"b":"c"}}"#,
+                      r#"{"a":{"b":"d","c":null}}"#,
+                      r#"{"a":{"b":"d"}}"#),
+                     (r#"{"a":[{"b":"c"}]}"#, r#"{"a":[1]}"#, r#"{"a":[1]}"#),
+                     (r#"["a","b"]"#, r#"["c","d"]"#, r#"["c","d"]"#),
+                     (r#"{"a":"b"}"#, r#"["c"]"#, r#"["c"]"#),
+                     (r#"{"a":"foo"}"#, r#"null"#, r#"null"#),
+                     (r#"{"a":"foo"}"#, r#""bar""#, r#""bar""#),
+                     (r#"{"e":null}"#, r#"{"a":1}"#, r#"{"e":null,"a":1}"#),
+                     (r#"[1,2]"#, r#"{"a":"b","c":null}"#, r#"{"a":"b"}"#),
+                     (r#"{}"#, r#"{"a":{"bb":{"ccc":null}}}"#, r#"{"a":{"bb":{}}}"#)];
+        for &(target, patch, result) in cases.iter() {
+            assert_eq!(patched(target, patch), result);
+        }
+    }
+
+    static CONFIG: &'static str = r#"{
+        "sampling": {"interval": 30, "sensors": ["pm25", "temperature"]},
+        "server": {"url": "https://example.com/api", "token": "abc"},
+        "debug": false
+    }"#;
+
+    #[test]
+    fn configuration() {
+        let patch = r#"{"sampling": {"interval": 60}, "server": {"token": null}, "led": true}"#;
+        assert_eq!(patched(CONFIG, patch),
+                   r#"{"sampling":{"interval":60,"sensors":["pm25","temperature"]},"#.to_string() +
+                   r#""server":{"url":"https://example.com/api"},"debug":false,"led":true}"#);
+        // Escaped names are written back as they are decoded.
+        assert_eq!(patched(r#"{"a\/b": 1}"#, r#"{"a": 2}"#), r#"{"a/b":1,"a":2}"#);
+    }
+
+    #[test]
+    fn dry_run() {
+        let patch = r#"{"sampling": {"interval": 60, "sensors": ["pm25", "temperature"]},
+                        "server": {"token": null, "url/v2~": null}, "led": true,
+                        "debug": {"x": null}}"#;
+        assert_eq!(changes(CONFIG, patch),
+                   vec![("/sampling/interval".to_string(), MergeChange::Replaced),
+                        ("/server/token".to_string(), MergeChange::Removed),
+                        ("/led".to_string(), MergeChange::Added),
+                        ("/debug".to_string(), MergeChange::Replaced)]);
+
+        // Patches that change nothing.
+        assert!(changes(CONFIG, r#"{"debug": false, "missing": null}"#).is_empty());
+        assert!(changes(CONFIG, r#"{"sampling": {"sensors": ["pm25", "temperature"]}}"#)
+            .is_empty());
+        assert!(changes(r#"{"a": {"b": {}}}"#, r#"{"a": {"b": {"c": null}}}"#).is_empty());
+        assert_eq!(changes(r#"{"a": 1, "b": {}}"#, r#"{"b": {"a": 1}}"#),
+                   vec![("/b/a".to_string(), MergeChange::Added)]);
+
+        // Replacing the whole document.
+        assert_eq!(changes(CONFIG, "[]"), vec![("".to_string(), MergeChange::Replaced)]);
+        assert!(changes("[]", "[]").is_empty());
+        assert_eq!(changes("[]", r#"{"a": {"b": null}}"#),
+                   vec![("".to_string(), MergeChange::Replaced)]);
+        assert!(changes(r#"{"a": {}}"#, r#"{"a": {"b": null}}"#).is_empty());
+
+        let mut target_nodes = [JsonNode::new(); 32];
+        let mut patch_nodes = [JsonNode::new(); 32];
+        let target = JsonValue::parse(&mut JsonTokenizer::new(CONFIG), &mut target_nodes)
+            .unwrap();
+        let patch = JsonValue::parse(&mut JsonTokenizer::new(patch), &mut patch_nodes).unwrap();
+        let mut top_level = Vec::new();
+        merge_changes(target, patch, |path, _| {
+            if path.parent().map_or(false, |parent| parent.key().is_none()) {
+                top_level.push(path.key().unwrap().to_string());
+            }
+        });
+        assert_eq!(top_level, ["led", "debug"]);
+    }
+}
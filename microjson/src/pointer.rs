@@ -0,0 +1,215 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// RFC 6901 JSON Pointer evaluation over the token stream: unrelated subtrees
+// are skipped and nothing is allocated.
+
+use core::str;
+use json::{skip_value, JsonError, JsonStr, JsonToken, JsonTokenizer};
+
+/// The value a JSON Pointer refers to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointerMatch<'a> {
+    /// A string, number, boolean or null.
+    Value(JsonToken<'a>),
+    /// The text of an object or array.
+    Document(&'a str),
+}
+
+// Checks that `~` is only used in the `~0` and `~1` escapes.
+fn valid_pointer(pointer: &str) -> bool {
+    let mut chars = pointer.chars();
+    while let Some(c) = chars.next() {
+        if c == '~' {
+            match chars.next() {
+                Some('0') | Some('1') => {}
+                _ => return false,
+            }
+        }
+    }
+    true
+}
+
+// Iterates over the characters of a reference token, unescaping `~0` and `~1`.
+struct Unescaped<'p> {
+    chars: str::Chars<'p>,
+}
+
+impl<'p> Iterator for Unescaped<'p> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self.chars.next() {
+            Some('~') => {
+                match self.chars.next() {
+                    Some('0') => Some('~'),
+                    _ => Some('/'),
+                }
+            }
+            c => c,
+        }
+    }
+}
+
+fn key_matches(key: JsonStr, segment: &str) -> bool {
+    key.chars().eq(Unescaped { chars: segment.chars() })
+}
+
+// Array indices are written in decimal, without leading zeros.
+fn array_index(segment: &str) -> Option<usize> {
+    let bytes = segment.as_bytes();
+    if bytes.is_empty() || (bytes.len() > 1 && bytes[0] == b'0') {
+        return None;
+    }
+    if bytes.iter().any(|&c| c < b'0' || c > b'9') {
+        return None;
+    }
+    segment.parse().ok()
+}
+
+impl<'a> JsonTokenizer<'a> {
+    /// Evaluates a JSON Pointer like `/value/0/@iot.id` against the next
+    /// value in the input, in a single pass.
+    ///
+    /// Returns `None` if nothing matches or if the pointer is invalid, in
+    /// which case the tokenizer is left in the middle of the value.
+    pub fn find_pointer(&mut self, pointer: &str) -> Result<Option<PointerMatch<'a>>, JsonError> {
+        if !valid_pointer(pointer) || !(pointer.is_empty() || pointer.starts_with('/')) {
+            return Ok(None);
+        }
+
+        let mut segments = pointer.split('/').skip(1);
+        let mut token = self.next_token()?;
+        loop {
+            let segment = match segments.next() {
+                Some(segment) => segment,
+                None => return self.capture(token).map(Some),
+            };
+            match token {
+                JsonToken::StartObject => {
+                    loop {
+                        let key = match self.next_token()? {
+                            JsonToken::PropertyName(key) => key,
+                            _ => return Ok(None),
+                        };
+                        let value = self.next_token()?;
+                        if key_matches(key, segment) {
+                            token = value;
+                            break;
+                        }
+                        skip_value(value, self)?;
+                    }
+                }
+                JsonToken::StartArray => {
+                    let index = match array_index(segment) {
+                        Some(index) => index,
+                        None => return Ok(None),
+                    };
+                    let mut i = 0;
+                    loop {
+                        let value = self.next_token()?;
+                        if value == JsonToken::EndArray {
+                            return Ok(None);
+                        }
+                        if i == index {
+                            token = value;
+                            break;
+                        }
+                        skip_value(value, self)?;
+                        i += 1;
+                    }
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    // Turns the value starting with `token` into a match, consuming it.
+    fn capture(&mut self, token: JsonToken<'a>) -> Result<PointerMatch<'a>, JsonError> {
+        match token {
+            JsonToken::StartObject | JsonToken::StartArray => {
+                // The opening character was just consumed.
+                let start = self.offset() - 1;
+                skip_value(token, self)?;
+                let text = &self.input()[start..self.offset()];
+                // The input came from a `str`, and the span starts and ends
+                // on ASCII characters.
+                Ok(PointerMatch::Document(unsafe { str::from_utf8_unchecked(text) }))
+            }
+            _ => Ok(PointerMatch::Value(token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use json::{JsonStr, JsonToken, JsonTokenizer};
+    use pointer::PointerMatch;
+
+    static RESPONSE: &'static str = r#"{
+  "@iot.count": 2,
+  "value": [
+    {
+      "@iot.id": "1",
+      "unitOfMeasurement": {"name": "PM 2.5", "symbol": "µg/m³"},
+      "observedArea": null
+    },
+    {"@iot.id": "2", "result": [12.5, [true, false]]}
+  ],
+  "a/b": 1,
+  "m~n": 2,
+  "": 3,
+  "\u0041": 4
+}"#;
+
+    fn find(pointer: &str) -> Option<PointerMatch<'static>> {
+        JsonTokenizer::new(RESPONSE).find_pointer(pointer).unwrap()
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(find("/@iot.count"),
+                   Some(PointerMatch::Value(JsonToken::Integer(2))));
+        assert_eq!(find("/value/0/@iot.id"),
+                   Some(PointerMatch::Value(JsonToken::String(JsonStr::new("1")))));
+        assert_eq!(find("/value/0/unitOfMeasurement/symbol"),
+                   Some(PointerMatch::Value(JsonToken::String(JsonStr::new("µg/m³")))));
+        assert_eq!(find("/value/0/observedArea"),
+                   Some(PointerMatch::Value(JsonToken::Null)));
+        assert_eq!(find("/value/1/result/1/0"),
+                   Some(PointerMatch::Value(JsonToken::True)));
+        assert_eq!(find("/a~1b"), Some(PointerMatch::Value(JsonToken::Integer(1))));
+        assert_eq!(find("/m~0n"), Some(PointerMatch::Value(JsonToken::Integer(2))));
+        assert_eq!(find("/"), Some(PointerMatch::Value(JsonToken::Integer(3))));
+        assert_eq!(find("/A"), Some(PointerMatch::Value(JsonToken::Integer(4))));
+    }
+
+    #[test]
+    fn documents() {
+        assert_eq!(find(""), Some(PointerMatch::Document(RESPONSE)));
+        let second = r#"{"@iot.id": "2", "result": [12.5, [true, false]]}"#;
+        assert_eq!(find("/value/1"), Some(PointerMatch::Document(second)));
+        assert_eq!(find("/value/1/result"),
+                   Some(PointerMatch::Document("[12.5, [true, false]]")));
+    }
+
+    #[test]
+    fn no_match() {
+        let pointers = ["/missing",
+                        "/value/2",
+                        "/value/-",
+                        "/value/01",
+                        "/value/+1",
+                        "/value/name",
+                        "/@iot.count/0",
+                        "/value/0/@iot.id/x",
+                        "/a/b",
+                        "/m~2n",
+                        "value"];
+        for pointer in pointers.iter() {
+            assert_eq!(find(pointer), None);
+        }
+    }
+}
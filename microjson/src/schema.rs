@@ -0,0 +1,561 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Validation of documents against schemas declared as static tables, in a
+// single pass over the tokens and without allocating.
+
+use core::fmt::{self, Write};
+use json::{skip_value, JsonError, JsonStr, JsonToken, JsonTokenizer};
+use writer::SliceWriter;
+
+/// Maximum length of the JSON Pointer of a violation, longer ones are
+/// truncated.
+pub const MAX_SCHEMA_PATH: usize = 64;
+
+/// Maximum number of required properties in a `JsonSchema::Object`.
+pub const MAX_REQUIRED_PROPERTIES: usize = 64;
+
+/// The expected shape of a value. Schemas are meant to be declared as
+/// statics:
+///
+/// ```ignore
+/// static SAMPLING: JsonSchema = JsonSchema::Object {
+///     properties: &[SchemaProperty {
+///                       name: "interval",
+///                       required: true,
+///                       schema: JsonSchema::Integer { min: 1, max: 3600 },
+///                   },
+///                   SchemaProperty {
+///                       name: "mode",
+///                       required: false,
+///                       schema: JsonSchema::Enum(&["fast", "slow"]),
+///                   }],
+///     additional: false,
+/// };
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub enum JsonSchema {
+    /// Anything, which is not looked into.
+    Any,
+    Null,
+    Bool,
+    /// An integer in `min..max`, bounds included.
+    Integer { min: i64, max: i64 },
+    /// A number in `min..max`, bounds included. Integers are numbers too.
+    Number { min: f64, max: f64 },
+    /// A string of `min_len` to `max_len` characters, bounds included.
+    String { min_len: usize, max_len: usize },
+    /// One of the given strings.
+    Enum(&'static [&'static str]),
+    /// An array of `min_len` to `max_len` elements, all matching `items`.
+    Array {
+        items: &'static JsonSchema,
+        min_len: usize,
+        max_len: usize,
+    },
+    /// An object with the given properties, of which at most
+    /// `MAX_REQUIRED_PROPERTIES` can be required. Other members are an error
+    /// unless `additional` is set.
+    Object {
+        properties: &'static [SchemaProperty],
+        additional: bool,
+    },
+}
+
+/// A member of a `JsonSchema::Object`.
+#[derive(Clone, Copy, Debug)]
+pub struct SchemaProperty {
+    pub name: &'static str,
+    pub required: bool,
+    pub schema: JsonSchema,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchemaErrorKind {
+    /// The document is not valid JSON.
+    Json(JsonError),
+    WrongType,
+    /// A required member is missing, the path is the one it should have.
+    MissingKey,
+    UnknownKey,
+    /// An integer or a number is out of bounds.
+    OutOfRange,
+    /// A string or an array is shorter than allowed.
+    TooShort,
+    /// A string or an array is longer than allowed.
+    TooLong,
+    NotInEnum,
+    /// The schema of the object has more than `MAX_REQUIRED_PROPERTIES`
+    /// required properties, so it can't be checked.
+    TooManyRequired,
+}
+
+impl fmt::Display for SchemaErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchemaErrorKind::Json(ref err) => write!(f, "{}", err),
+            SchemaErrorKind::WrongType => f.write_str("wrong type"),
+            SchemaErrorKind::MissingKey => f.write_str("missing required key"),
+            SchemaErrorKind::UnknownKey => f.write_str("unknown key"),
+            SchemaErrorKind::OutOfRange => f.write_str("number out of range"),
+            SchemaErrorKind::TooShort => f.write_str("too short"),
+            SchemaErrorKind::TooLong => f.write_str("too long"),
+            SchemaErrorKind::NotInEnum => f.write_str("not one of the allowed values"),
+            SchemaErrorKind::TooManyRequired => f.write_str("too many required properties"),
+        }
+    }
+}
+
+/// The first violation of a schema found in a document.
+#[derive(Copy)]
+pub struct SchemaError {
+    pub kind: SchemaErrorKind,
+    /// Byte offset of the token in violation.
+    pub offset: usize,
+    path: [u8; MAX_SCHEMA_PATH],
+    path_len: usize,
+}
+
+impl SchemaError {
+    /// The JSON Pointer of the value in violation, like `/sampling/interval`.
+    pub fn path(&self) -> &str {
+        // Only complete `str`s are written to the path.
+        unsafe { ::core::str::from_utf8_unchecked(&self.path[..self.path_len]) }
+    }
+}
+
+impl Clone for SchemaError {
+    fn clone(&self) -> SchemaError {
+        *self
+    }
+}
+
+impl PartialEq for SchemaError {
+    fn eq(&self, other: &SchemaError) -> bool {
+        self.kind == other.kind && self.offset == other.offset && self.path() == other.path()
+    }
+}
+
+impl fmt::Debug for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SchemaError")
+            .field("kind", &self.kind)
+            .field("offset", &self.offset)
+            .field("path", &self.path())
+            .finish()
+    }
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at \"{}\"", self.kind, self.path())
+    }
+}
+
+// One step of the path to the value being checked.
+#[derive(Clone, Copy)]
+enum Step<'a> {
+    Root,
+    Key(JsonStr<'a>),
+    Name(&'static str),
+    Index(usize),
+}
+
+struct Segment<'p, 'a: 'p> {
+    step: Step<'a>,
+    parent: Option<&'p Segment<'p, 'a>>,
+}
+
+impl<'p, 'a> Segment<'p, 'a> {
+    fn child(&'p self, step: Step<'a>) -> Segment<'p, 'a> {
+        Segment {
+            step: step,
+            parent: Some(self),
+        }
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> fmt::Result {
+        if let Some(parent) = self.parent {
+            parent.write(out)?;
+        }
+        match self.step {
+            Step::Root => Ok(()),
+            Step::Index(index) => write!(out, "/{}", index),
+            Step::Key(key) => write_token(out, key.chars()),
+            Step::Name(name) => write_token(out, name.chars()),
+        }
+    }
+
+    fn error(&self, kind: SchemaErrorKind, offset: usize) -> SchemaError {
+        let mut path = [0u8; MAX_SCHEMA_PATH];
+        let path_len = {
+            let mut out = SliceWriter::new(&mut path);
+            // A path too long is cut at the last character that fits.
+            let _ = self.write(&mut out);
+            out.len()
+        };
+        SchemaError {
+            kind: kind,
+            offset: offset,
+            path: path,
+            path_len: path_len,
+        }
+    }
+}
+
+// Writes a reference token of a JSON Pointer, escaping `~` and `/`.
+fn write_token<W: Write, I: Iterator<Item = char>>(out: &mut W, chars: I) -> fmt::Result {
+    out.write_char('/')?;
+    for c in chars {
+        match c {
+            '~' => out.write_str("~0")?,
+            '/' => out.write_str("~1")?,
+            _ => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+fn next_token<'a>(tokenizer: &mut JsonTokenizer<'a>,
+                  path: &Segment)
+                  -> Result<JsonToken<'a>, SchemaError> {
+    tokenizer.next_token().map_err(|err| path.error(SchemaErrorKind::Json(err), err.offset))
+}
+
+impl JsonSchema {
+    /// Reads the next value of `tokenizer` and checks it against this
+    /// schema. The tokenizer is left after the value if it is valid, and
+    /// where the first violation was found otherwise.
+    pub fn validate(&self, tokenizer: &mut JsonTokenizer) -> Result<(), SchemaError> {
+        let root = Segment {
+            step: Step::Root,
+            parent: None,
+        };
+        let token = next_token(tokenizer, &root)?;
+        if token == JsonToken::Done {
+            let offset = tokenizer.offset();
+            return Err(root.error(SchemaErrorKind::WrongType, offset));
+        }
+        self.check(token, tokenizer, &root)
+    }
+
+    fn check<'a>(&self,
+                 token: JsonToken<'a>,
+                 tokenizer: &mut JsonTokenizer<'a>,
+                 path: &Segment)
+                 -> Result<(), SchemaError> {
+        let offset = tokenizer.token_offset();
+        let valid = match (*self, token) {
+            (JsonSchema::Any, _) => {
+                return skip_value(token, tokenizer)
+                    .map_err(|err| path.error(SchemaErrorKind::Json(err), err.offset));
+            }
+            (JsonSchema::Null, JsonToken::Null) |
+            (JsonSchema::Bool, JsonToken::True) |
+            (JsonSchema::Bool, JsonToken::False) => Ok(()),
+            (JsonSchema::Integer { min, max }, JsonToken::Integer(value)) => {
+                if value < min || value > max {
+                    Err(SchemaErrorKind::OutOfRange)
+                } else {
+                    Ok(())
+                }
+            }
+            (JsonSchema::Number { min, max }, JsonToken::Integer(_)) |
+            (JsonSchema::Number { min, max }, JsonToken::Number(_)) |
+            (JsonSchema::Number { min, max }, JsonToken::RawNumber(_)) => {
+                let value = match token {
+                    JsonToken::Integer(value) => value as f64,
+                    JsonToken::Number(value) => value,
+                    JsonToken::RawNumber(text) => text.parse::<f64>().unwrap_or(0.0),
+                    _ => 0.0,
+                };
+                if value < min || value > max {
+                    Err(SchemaErrorKind::OutOfRange)
+                } else {
+                    Ok(())
+                }
+            }
+            (JsonSchema::String { min_len, max_len }, JsonToken::String(value)) => {
+                let len = value.chars().count();
+                if len < min_len {
+                    Err(SchemaErrorKind::TooShort)
+                } else if len > max_len {
+                    Err(SchemaErrorKind::TooLong)
+                } else {
+                    Ok(())
+                }
+            }
+            (JsonSchema::Enum(values), JsonToken::String(value)) => {
+                if values.iter().any(|candidate| value == *candidate) {
+                    Ok(())
+                } else {
+                    Err(SchemaErrorKind::NotInEnum)
+                }
+            }
+            (JsonSchema::Array { items, min_len, max_len }, JsonToken::StartArray) => {
+                let mut len = 0;
+                loop {
+                    let token = next_token(tokenizer, path)?;
+                    if token == JsonToken::EndArray {
+                        break;
+                    }
+                    if len == max_len {
+                        return Err(path.error(SchemaErrorKind::TooLong, tokenizer.token_offset()));
+                    }
+                    items.check(token, tokenizer, &path.child(Step::Index(len)))?;
+                    len += 1;
+                }
+                if len < min_len {
+                    Err(SchemaErrorKind::TooShort)
+                } else {
+                    Ok(())
+                }
+            }
+            (JsonSchema::Object { properties, additional }, JsonToken::StartObject) => {
+                return check_object(properties, additional, tokenizer, path);
+            }
+            _ => Err(SchemaErrorKind::WrongType),
+        };
+        valid.map_err(|kind| path.error(kind, offset))
+    }
+}
+
+fn check_object<'a>(properties: &[SchemaProperty],
+                    additional: bool,
+                    tokenizer: &mut JsonTokenizer<'a>,
+                    path: &Segment)
+                    -> Result<(), SchemaError> {
+    if properties.iter().filter(|property| property.required).count() >
+       MAX_REQUIRED_PROPERTIES {
+        let offset = tokenizer.token_offset();
+        return Err(path.error(SchemaErrorKind::TooManyRequired, offset));
+    }
+    // The required properties seen so far, one bit each in their order.
+    let mut seen = 0u64;
+    loop {
+        let key = match next_token(tokenizer, path)? {
+            JsonToken::PropertyName(key) => key,
+            _ => break,
+        };
+        let key_offset = tokenizer.token_offset();
+        let member = path.child(Step::Key(key));
+        let token = next_token(tokenizer, &member)?;
+        match properties.iter().position(|property| key == property.name) {
+            Some(index) => {
+                if properties[index].required {
+                    let bit = properties[..index]
+                        .iter()
+                        .filter(|property| property.required)
+                        .count();
+                    seen |= 1 << bit;
+                }
+                properties[index].schema.check(token, tokenizer, &member)?;
+            }
+            None if additional => {
+                skip_value(token, tokenizer)
+                    .map_err(|err| member.error(SchemaErrorKind::Json(err), err.offset))?;
+            }
+            None => return Err(member.error(SchemaErrorKind::UnknownKey, key_offset)),
+        }
+    }
+    // Reported at the end of the object.
+    let offset = tokenizer.token_offset();
+    for (bit, property) in properties.iter().filter(|property| property.required).enumerate() {
+        if seen & (1 << bit) == 0 {
+            let member = path.child(Step::Name(property.name));
+            return Err(member.error(SchemaErrorKind::MissingKey, offset));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use core::i64;
+    use json::{JsonErrorKind, JsonToken, JsonTokenizer};
+    use schema::{JsonSchema, SchemaError, SchemaErrorKind, SchemaProperty,
+                 MAX_REQUIRED_PROPERTIES, MAX_SCHEMA_PATH};
+
+    static SENSOR: JsonSchema = JsonSchema::Object {
+        properties: &[SchemaProperty {
+                          name: "name",
+                          required: true,
+                          schema: JsonSchema::String {
+                              min_len: 1,
+                              max_len: 8,
+                          },
+                      },
+                      SchemaProperty {
+                          name: "scale",
+                          required: false,
+                          schema: JsonSchema::Number {
+                              min: 0.0,
+                              max: 10.0,
+                          },
+                      }],
+        additional: true,
+    };
+
+    static CONFIG: JsonSchema = JsonSchema::Object {
+        properties: &[SchemaProperty {
+                          name: "interval",
+                          required: true,
+                          schema: JsonSchema::Integer {
+                              min: 1,
+                              max: 3600,
+                          },
+                      },
+                      SchemaProperty {
+                          name: "mode",
+                          required: false,
+                          schema: JsonSchema::Enum(&["fast", "slow"]),
+                      },
+                      SchemaProperty {
+                          name: "debug",
+                          required: false,
+                          schema: JsonSchema::Bool,
+                      },
+                      SchemaProperty {
+                          name: "proxy",
+                          required: false,
+                          schema: JsonSchema::Null,
+                      },
+                      SchemaProperty {
+                          name: "sensors",
+                          required: true,
+                          schema: JsonSchema::Array {
+                              items: &SENSOR,
+                              min_len: 1,
+                              max_len: 2,
+                          },
+                      },
+                      SchemaProperty {
+                          name: "extra",
+                          required: false,
+                          schema: JsonSchema::Any,
+                      }],
+        additional: false,
+    };
+
+    const OPTIONAL: SchemaProperty = SchemaProperty {
+        name: "o",
+        required: false,
+        schema: JsonSchema::Any,
+    };
+
+    const REQUIRED: SchemaProperty = SchemaProperty {
+        name: "r",
+        required: true,
+        schema: JsonSchema::Null,
+    };
+
+    // A required property after more than 64 others.
+    static LAST_REQUIRED: [SchemaProperty; 66] =
+        [OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL,
+         OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL,
+         OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL,
+         OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL,
+         OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL,
+         OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL,
+         OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL, OPTIONAL,
+         OPTIONAL, OPTIONAL, REQUIRED];
+
+    static TOO_MANY_REQUIRED: [SchemaProperty; MAX_REQUIRED_PROPERTIES + 1] =
+        [REQUIRED; MAX_REQUIRED_PROPERTIES + 1];
+
+    fn validate(text: &str) -> Result<(), SchemaError> {
+        CONFIG.validate(&mut JsonTokenizer::new(text))
+    }
+
+    #[test]
+    fn valid_documents() {
+        let mut tokenizer = JsonTokenizer::new(r#"{"interval": 60, "mode": "slow", "debug": true,
+            "proxy": null, "sensors": [{"name": "pm25", "other": [1]}, {"name": "té"}],
+            "extra": {"anything": [1, 2, {}]}}"#);
+        CONFIG.validate(&mut tokenizer).unwrap();
+        // The tokenizer is left after the value.
+        assert_eq!(tokenizer.next_token().unwrap(), JsonToken::Done);
+        assert!(validate(r#"{"sensors": [{"name": "pm25", "scale": 1}], "interval": 1}"#).is_ok());
+        assert!(JsonSchema::Integer {
+                min: i64::MIN,
+                max: i64::MAX,
+            }
+            .validate(&mut JsonTokenizer::new("-5"))
+            .is_ok());
+    }
+
+    #[test]
+    fn violations() {
+        let cases = [(r#"[]"#, SchemaErrorKind::WrongType, "", 0),
+                     (r#"{"interval": 0}"#, SchemaErrorKind::OutOfRange, "/interval", 13),
+                     (r#"{"interval": 1.5}"#, SchemaErrorKind::WrongType, "/interval", 13),
+                     (r#"{"mode": "eco"}"#, SchemaErrorKind::NotInEnum, "/mode", 9),
+                     (r#"{"debug": 1}"#, SchemaErrorKind::WrongType, "/debug", 10),
+                     (r#"{"a/b~": 1}"#, SchemaErrorKind::UnknownKey, "/a~1b~0", 1),
+                     (r#"{"sensors": []}"#, SchemaErrorKind::TooShort, "/sensors", 12),
+                     (r#"{"sensors": [{"name": "a"}, {"name": ""}]}"#,
+                      SchemaErrorKind::TooShort,
+                      "/sensors/1/name",
+                      37),
+                     (r#"{"sensors": [{"name": "a", "scale": -1}]}"#,
+                      SchemaErrorKind::OutOfRange,
+                      "/sensors/0/scale",
+                      36),
+                     (r#"{"sensors": [{"name": "a"}, {"name": "b"}, {"name": "c"}]}"#,
+                      SchemaErrorKind::TooLong,
+                      "/sensors",
+                      43)];
+        for &(text, kind, path, offset) in cases.iter() {
+            let err = validate(text).unwrap_err();
+            assert_eq!((err.kind, err.path(), err.offset), (kind, path, offset));
+        }
+
+        // Required keys are checked at the end of the object.
+        let text = r#"{"sensors": [{"name": "pm25"}]}"#;
+        let err = validate(text).unwrap_err();
+        assert_eq!((err.kind, err.path(), err.offset),
+                   (SchemaErrorKind::MissingKey, "/interval", text.len() - 1));
+
+        let err = validate(r#"{"sensors": [{"name": x"#).unwrap_err();
+        match err.kind {
+            SchemaErrorKind::Json(json) => {
+                assert_eq!(json.kind, JsonErrorKind::UnexpectedCharacter);
+            }
+            _ => panic!("Expected a JSON error"),
+        }
+        assert_eq!(err.path(), "/sensors/0/name");
+    }
+
+    #[test]
+    fn many_properties() {
+        let schema = JsonSchema::Object {
+            properties: &LAST_REQUIRED,
+            additional: false,
+        };
+        assert!(schema.validate(&mut JsonTokenizer::new(r#"{"r": null}"#)).is_ok());
+        let err = schema.validate(&mut JsonTokenizer::new(r#"{"o": 1}"#)).unwrap_err();
+        assert_eq!((err.kind, err.path(), err.offset), (SchemaErrorKind::MissingKey, "/r", 7));
+
+        let schema = JsonSchema::Object {
+            properties: &TOO_MANY_REQUIRED,
+            additional: false,
+        };
+        let err = schema.validate(&mut JsonTokenizer::new(r#"[{"r": null}]"#)).unwrap_err();
+        assert_eq!((err.kind, err.path(), err.offset), (SchemaErrorKind::WrongType, "", 0));
+        let err = schema.validate(&mut JsonTokenizer::new(r#" {"r": null}"#)).unwrap_err();
+        assert_eq!((err.kind, err.path(), err.offset),
+                   (SchemaErrorKind::TooManyRequired, "", 1));
+    }
+
+    #[test]
+    fn long_paths() {
+        let key = "0123456789".repeat(7);
+        let text = format!("{{\"{}\": 1}}", key);
+        let err = validate(&text).unwrap_err();
+        assert_eq!((err.kind, err.offset), (SchemaErrorKind::UnknownKey, 1));
+        assert_eq!(err.path().len(), MAX_SCHEMA_PATH);
+        assert_eq!(err.path(), &format!("/{}", key)[..MAX_SCHEMA_PATH]);
+    }
+}
@@ -0,0 +1,46 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/// Maximum number of nested objects and arrays.
+pub const MAX_DEPTH: usize = 256;
+
+// Keeps track of the open objects and arrays, using one bit per level.
+#[derive(Clone, Copy)]
+pub struct NestingStack {
+    bits: [u32; MAX_DEPTH / 32],
+    pub depth: usize,
+}
+
+impl NestingStack {
+    pub fn new() -> Self {
+        NestingStack {
+            bits: [0; MAX_DEPTH / 32],
+            depth: 0,
+        }
+    }
+
+    pub fn push(&mut self, object: bool) -> Result<(), ()> {
+        if self.depth == MAX_DEPTH {
+            return Err(());
+        }
+        let (word, bit) = (self.depth / 32, self.depth % 32);
+        if object {
+            self.bits[word] |= 1 << bit;
+        } else {
+            self.bits[word] &= !(1 << bit);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) {
+        self.depth -= 1;
+    }
+
+    // Whether the innermost open container is an object.
+    pub fn in_object(&self) -> bool {
+        let top = self.depth - 1;
+        self.bits[top / 32] & (1 << (top % 32)) != 0
+    }
+}
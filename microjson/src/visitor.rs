@@ -0,0 +1,385 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Event based parsing: tokens are dispatched to the callbacks of a visitor.
+
+use json::{JsonError, JsonStr, JsonStreamTokenizer, JsonToken, JsonTokenizer};
+
+/// What to do after an event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Visit {
+    Continue,
+    /// Skips the object, array or string in fragments that was just started,
+    /// or the value of the property that was just named. The end of what is
+    /// skipped is not reported. Same as `Continue` for other events.
+    Skip,
+    /// Ends the visit, leaving the rest of the input unread.
+    Stop,
+}
+
+/// Callbacks for the events of a document. All of them continue by default.
+pub trait JsonVisitor<'a> {
+    fn on_object_start(&mut self) -> Visit {
+        Visit::Continue
+    }
+
+    fn on_object_end(&mut self) -> Visit {
+        Visit::Continue
+    }
+
+    fn on_array_start(&mut self) -> Visit {
+        Visit::Continue
+    }
+
+    fn on_array_end(&mut self) -> Visit {
+        Visit::Continue
+    }
+
+    /// A property name, followed by the events of its value.
+    fn on_key(&mut self, _key: JsonStr<'a>) -> Visit {
+        Visit::Continue
+    }
+
+    fn on_string(&mut self, _value: JsonStr<'a>) -> Visit {
+        Visit::Continue
+    }
+
+    /// A string returned in fragments, see
+    /// `JsonStreamTokenizer::string_fragments()`.
+    fn on_string_start(&mut self) -> Visit {
+        Visit::Continue
+    }
+
+    fn on_string_fragment(&mut self, _fragment: JsonStr<'a>) -> Visit {
+        Visit::Continue
+    }
+
+    fn on_string_end(&mut self) -> Visit {
+        Visit::Continue
+    }
+
+    /// Integers are reported as numbers unless this is implemented.
+    fn on_integer(&mut self, value: i64) -> Visit {
+        self.on_number(value as f64)
+    }
+
+    fn on_number(&mut self, _value: f64) -> Visit {
+        Visit::Continue
+    }
+
+    /// Raw numbers are converted and reported as numbers unless this is
+    /// implemented, see `JsonTokenizer::raw_numbers()`.
+    fn on_raw_number(&mut self, text: &'a str) -> Visit {
+        match text.parse::<f64>() {
+            Ok(value) => self.on_number(value),
+            Err(_) => Visit::Continue,
+        }
+    }
+
+    fn on_bool(&mut self, _value: bool) -> Visit {
+        Visit::Continue
+    }
+
+    fn on_null(&mut self) -> Visit {
+        Visit::Continue
+    }
+}
+
+/// Dispatches the tokens of a document to a visitor, keeping track of the
+/// values being skipped. Tokens can come from any tokenizer, one at a time.
+pub struct JsonDispatcher {
+    // Number of open containers being skipped.
+    skipped: usize,
+    // Whether the next value is skipped.
+    skip_value: bool,
+    finished: bool,
+}
+
+impl JsonDispatcher {
+    pub fn new() -> Self {
+        JsonDispatcher {
+            skipped: 0,
+            skip_value: false,
+            finished: false,
+        }
+    }
+
+    /// Whether the document is complete, or the visitor stopped.
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Dispatches one token, returning `finished()`.
+    pub fn dispatch<'a, V: JsonVisitor<'a>>(&mut self,
+                                           token: JsonToken<'a>,
+                                           visitor: &mut V)
+                                           -> bool {
+        if self.finished || token == JsonToken::Done {
+            self.finished = true;
+            return true;
+        }
+        let container = match token {
+            JsonToken::StartObject | JsonToken::StartArray | JsonToken::StartString => true,
+            _ => false,
+        };
+        if self.skip_value {
+            self.skip_value = false;
+            if container {
+                self.skipped = 1;
+            }
+            return false;
+        }
+        if self.skipped > 0 {
+            match token {
+                JsonToken::StartObject | JsonToken::StartArray | JsonToken::StartString => {
+                    self.skipped += 1
+                }
+                JsonToken::EndObject | JsonToken::EndArray | JsonToken::EndString => {
+                    self.skipped -= 1
+                }
+                _ => {}
+            }
+            return false;
+        }
+
+        let visit = match token {
+            JsonToken::StartObject => visitor.on_object_start(),
+            JsonToken::EndObject => visitor.on_object_end(),
+            JsonToken::StartArray => visitor.on_array_start(),
+            JsonToken::EndArray => visitor.on_array_end(),
+            JsonToken::PropertyName(key) => visitor.on_key(key),
+            JsonToken::String(value) => visitor.on_string(value),
+            JsonToken::Integer(value) => visitor.on_integer(value),
+            JsonToken::Number(value) => visitor.on_number(value),
+            JsonToken::RawNumber(text) => visitor.on_raw_number(text),
+            JsonToken::StartString => visitor.on_string_start(),
+            JsonToken::StringFragment(fragment) => visitor.on_string_fragment(fragment),
+            JsonToken::EndString => visitor.on_string_end(),
+            JsonToken::True => visitor.on_bool(true),
+            JsonToken::False => visitor.on_bool(false),
+            JsonToken::Null => visitor.on_null(),
+            JsonToken::Done => Visit::Stop,
+        };
+        match visit {
+            Visit::Continue => {}
+            Visit::Skip if container => self.skipped = 1,
+            Visit::Skip => {
+                if let JsonToken::PropertyName(_) = token {
+                    self.skip_value = true;
+                }
+            }
+            Visit::Stop => self.finished = true,
+        }
+        self.finished
+    }
+
+    /// Visits the rest of the document.
+    pub fn run<'a, V: JsonVisitor<'a>>(&mut self,
+                                      tokenizer: &mut JsonTokenizer<'a>,
+                                      visitor: &mut V)
+                                      -> Result<(), JsonError> {
+        while !self.dispatch(tokenizer.next_token()?, visitor) {}
+        Ok(())
+    }
+
+    /// Visits the tokens available in a stream, returning `finished()`.
+    /// When this returns `false`, the stream needs more input.
+    pub fn run_stream<V>(&mut self,
+                         tokenizer: &mut JsonStreamTokenizer,
+                         visitor: &mut V)
+                         -> Result<bool, JsonError>
+        where V: for<'t> JsonVisitor<'t>
+    {
+        while !self.finished {
+            match tokenizer.next_token()? {
+                Some(token) => self.dispatch(token, visitor),
+                None => return Ok(false),
+            };
+        }
+        Ok(true)
+    }
+}
+
+/// Visits a whole document.
+pub fn visit<'a, V: JsonVisitor<'a>>(tokenizer: &mut JsonTokenizer<'a>,
+                                    visitor: &mut V)
+                                    -> Result<(), JsonError> {
+    JsonDispatcher::new().run(tokenizer, visitor)
+}
+
+#[cfg(test)]
+mod test {
+
+    use collections::String;
+    use collections::string::ToString;
+    use collections::vec::Vec;
+    use json::{JsonStr, JsonStreamTokenizer, JsonTokenizer};
+    use visitor::{visit, JsonDispatcher, JsonVisitor, Visit};
+
+    // Logs the events, skipping or stopping at the given keys.
+    struct Logger {
+        events: Vec<String>,
+        skip: &'static str,
+        stop: &'static str,
+    }
+
+    impl Logger {
+        fn new(skip: &'static str, stop: &'static str) -> Self {
+            Logger {
+                events: Vec::new(),
+                skip: skip,
+                stop: stop,
+            }
+        }
+
+        fn log(&mut self, event: String) -> Visit {
+            let visit = if event == self.skip {
+                Visit::Skip
+            } else if event == self.stop {
+                Visit::Stop
+            } else {
+                Visit::Continue
+            };
+            self.events.push(event);
+            visit
+        }
+    }
+
+    impl<'a> JsonVisitor<'a> for Logger {
+        fn on_object_start(&mut self) -> Visit {
+            self.log("{".to_string())
+        }
+
+        fn on_object_end(&mut self) -> Visit {
+            self.log("}".to_string())
+        }
+
+        fn on_array_start(&mut self) -> Visit {
+            self.log("[".to_string())
+        }
+
+        fn on_array_end(&mut self) -> Visit {
+            self.log("]".to_string())
+        }
+
+        fn on_key(&mut self, key: JsonStr<'a>) -> Visit {
+            self.log(format!("{}:", key))
+        }
+
+        fn on_string(&mut self, value: JsonStr<'a>) -> Visit {
+            self.log(format!("\"{}\"", value))
+        }
+
+        fn on_string_start(&mut self) -> Visit {
+            self.log("\"...".to_string())
+        }
+
+        fn on_string_fragment(&mut self, fragment: JsonStr<'a>) -> Visit {
+            self.log(fragment.to_string())
+        }
+
+        fn on_string_end(&mut self) -> Visit {
+            self.log("...\"".to_string())
+        }
+
+        fn on_number(&mut self, value: f64) -> Visit {
+            self.log(format!("{}", value))
+        }
+
+        fn on_bool(&mut self, value: bool) -> Visit {
+            self.log(format!("{}", value))
+        }
+
+        fn on_null(&mut self) -> Visit {
+            self.log("null".to_string())
+        }
+    }
+
+    static TEXT: &'static str = r#"{"a": [1, {"b": null}], "c": {"d": [true]}, "e": "é"}"#;
+
+    fn events(skip: &'static str, stop: &'static str) -> Vec<String> {
+        let mut logger = Logger::new(skip, stop);
+        visit(&mut JsonTokenizer::new(TEXT), &mut logger).unwrap();
+        logger.events
+    }
+
+    #[test]
+    fn events_in_order() {
+        assert_eq!(events("", ""),
+                   ["{", "a:", "[", "1", "{", "b:", "null", "}", "]", "c:", "{", "d:", "[",
+                    "true", "]", "}", "e:", "\"é\"", "}"]);
+    }
+
+    #[test]
+    fn skip_and_stop() {
+        // Skipping a property.
+        assert_eq!(events("c:", ""),
+                   ["{", "a:", "[", "1", "{", "b:", "null", "}", "]", "c:", "e:", "\"é\"", "}"]);
+        // Skipping a container.
+        assert_eq!(events("[", ""),
+                   ["{", "a:", "[", "c:", "{", "d:", "[", "}", "e:", "\"é\"", "}"]);
+        // Skipping a scalar does nothing.
+        assert_eq!(events("null", "").len(), 19);
+        assert_eq!(events("", "null"), ["{", "a:", "[", "1", "{", "b:", "null"]);
+    }
+
+    #[test]
+    fn streams() {
+        let mut buffer = [0u8; 16];
+        let mut tokenizer = JsonStreamTokenizer::new(&mut buffer);
+        let mut dispatcher = JsonDispatcher::new();
+        let mut logger = Logger::new("c:", "");
+        for chunk in TEXT.as_bytes().chunks(5) {
+            assert_eq!(tokenizer.feed(chunk), chunk.len());
+            dispatcher.run_stream(&mut tokenizer, &mut logger).unwrap();
+        }
+        tokenizer.finish();
+        assert!(dispatcher.run_stream(&mut tokenizer, &mut logger).unwrap());
+        assert!(dispatcher.finished());
+        assert_eq!(logger.events, events("c:", ""));
+    }
+
+    #[test]
+    fn string_fragments() {
+        let text = r#"{"long": "0123456789abcdefghijklmnopqrstuvwxyz", "skip": "0123456789abcdef",
+                      "e": 1}"#;
+        let mut buffer = [0u8; 16];
+        let mut tokenizer = JsonStreamTokenizer::new(&mut buffer).string_fragments(true);
+        let mut dispatcher = JsonDispatcher::new();
+        let mut logger = Logger::new("skip:", "");
+        let mut input = text.as_bytes();
+        while !dispatcher.run_stream(&mut tokenizer, &mut logger).unwrap() {
+            let fed = tokenizer.feed(input);
+            input = &input[fed..];
+            if input.is_empty() {
+                tokenizer.finish();
+            }
+        }
+
+        let events = logger.events;
+        assert_eq!(&events[..3], ["{", "long:", "\"..."]);
+        let end = events.iter().position(|event| event == "...\"").unwrap();
+        assert!(end > 4);
+        assert_eq!(events[3..end].iter().fold(String::new(), |text, fragment| text + fragment),
+                   "0123456789abcdefghijklmnopqrstuvwxyz");
+        // The skipped string isn't reported.
+        assert_eq!(&events[end..], ["...\"", "skip:", "e:", "1", "}"]);
+
+        // Nor are the strings in fragments of a skipped container.
+        let text = r#"{"skip": {"x": "0123456789abcdefghijklmnopqrstuvwxyz", "y": 5}, "e": 1}"#;
+        let mut buffer = [0u8; 16];
+        let mut tokenizer = JsonStreamTokenizer::new(&mut buffer).string_fragments(true);
+        let mut dispatcher = JsonDispatcher::new();
+        let mut logger = Logger::new("skip:", "");
+        let mut input = text.as_bytes();
+        while !dispatcher.run_stream(&mut tokenizer, &mut logger).unwrap() {
+            let fed = tokenizer.feed(input);
+            input = &input[fed..];
+            if input.is_empty() {
+                tokenizer.finish();
+            }
+        }
+        assert_eq!(logger.events, ["{", "skip:", "e:", "1", "}"]);
+    }
+}
@@ -0,0 +1,653 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this file,
+// You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// A JSON writer that checks the structure of what it emits, so that the
+// output is always a single well formed document.
+
+use core::f64;
+use core::fmt::{self, Write};
+use binary::{BinaryEncoder, BinaryEncoding};
+use core::str;
+use json::{JsonStr, JsonToken, JsonTokenizer};
+use stack::NestingStack;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JsonWriteError {
+    /// The underlying writer failed, usually because the buffer is full.
+    Write,
+    /// A value was written in an object without a key.
+    ExpectedKey,
+    /// A key was written outside of an object, or right after another key.
+    UnexpectedKey,
+    /// The closed container is not the innermost open one.
+    Unbalanced,
+    /// The document is already complete.
+    Complete,
+    /// The document is not complete yet.
+    Incomplete,
+    NestingTooDeep,
+    /// NaN and infinite numbers can't be represented in JSON.
+    InvalidNumber,
+    /// An object has two members with the same name, see `write_canonical()`.
+    DuplicateKey,
+    /// A string started with `begin_binary()` hasn't been ended.
+    UnfinishedBinary,
+    /// A string started with `begin_string()` or `begin_key()` hasn't been
+    /// ended.
+    UnfinishedString,
+}
+
+impl fmt::Display for JsonWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            JsonWriteError::Write => "failed to write output",
+            JsonWriteError::ExpectedKey => "expected a key before the value",
+            JsonWriteError::UnexpectedKey => "unexpected key",
+            JsonWriteError::Unbalanced => "unbalanced object or array",
+            JsonWriteError::Complete => "document already complete",
+            JsonWriteError::Incomplete => "document not complete",
+            JsonWriteError::NestingTooDeep => "too many nested objects and arrays",
+            JsonWriteError::InvalidNumber => "number can't be represented in JSON",
+            JsonWriteError::DuplicateKey => "duplicate key",
+            JsonWriteError::UnfinishedBinary => "binary string not ended",
+            JsonWriteError::UnfinishedString => "string not ended",
+        })
+    }
+}
+
+impl From<fmt::Error> for JsonWriteError {
+    fn from(_: fmt::Error) -> JsonWriteError {
+        JsonWriteError::Write
+    }
+}
+
+/// A `fmt::Write` implementation over a fixed size buffer.
+pub struct SliceWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SliceWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        SliceWriter {
+            buffer: buffer,
+            len: 0,
+        }
+    }
+
+    /// The number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only complete `str`s are ever written.
+        unsafe { str::from_utf8_unchecked(self.as_bytes()) }
+    }
+}
+
+impl<'a> Write for SliceWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let bytes = s.as_bytes();
+        if bytes.len() > self.buffer.len() - self.len {
+            return Err(fmt::Error);
+        }
+        self.buffer[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+}
+
+// The escape sequence of a character, empty for the ones written as `\u00XX`.
+fn escape(c: char) -> Option<&'static str> {
+    match c {
+        '"' => Some("\\\""),
+        '\\' => Some("\\\\"),
+        '\n' => Some("\\n"),
+        '\r' => Some("\\r"),
+        '\t' => Some("\\t"),
+        '\u{8}' => Some("\\b"),
+        '\u{c}' => Some("\\f"),
+        '\u{0}'...'\u{1f}' => Some(""),
+        _ => None,
+    }
+}
+
+fn write_escape<W: Write>(out: &mut W, c: char, escape: &str) -> fmt::Result {
+    if escape.is_empty() {
+        write!(out, "\\u{:04x}", c as u32)
+    } else {
+        out.write_str(escape)
+    }
+}
+
+/// Writes a JSON string, with the quotes, escaping what needs to be.
+pub fn write_escaped<W: Write>(out: &mut W, value: &str) -> fmt::Result {
+    out.write_char('"')?;
+    write_escaped_text(out, value)?;
+    out.write_char('"')
+}
+
+// Same as `write_escaped()`, without the quotes.
+fn write_escaped_text<W: Write>(out: &mut W, value: &str) -> fmt::Result {
+    let bytes = value.as_bytes();
+    let mut start = 0;
+    for (i, &c) in bytes.iter().enumerate() {
+        // Escapes are all ASCII, so `i` is on a character boundary.
+        if let Some(escape) = escape(c as char) {
+            out.write_str(&value[start..i])?;
+            write_escape(out, c as char, escape)?;
+            start = i + 1;
+        }
+    }
+    out.write_str(&value[start..])
+}
+
+/// Same as `write_escaped()`, for decoded characters such as the ones of
+/// `JsonStr::chars()`.
+pub fn write_escaped_chars<W, I>(out: &mut W, chars: I) -> fmt::Result
+    where W: Write,
+          I: Iterator<Item = char>
+{
+    out.write_char('"')?;
+    for c in chars {
+        match escape(c) {
+            Some(escape) => write_escape(out, c, escape)?,
+            None => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// Writes a string read by a tokenizer, decoding and escaping it again only
+/// if it has escapes.
+pub fn write_json_str<W: Write>(out: &mut W, value: JsonStr) -> fmt::Result {
+    match value.as_str() {
+        Some(text) => write_escaped(out, text),
+        None => write_escaped_chars(out, value.chars()),
+    }
+}
+
+// Remembers that a write failed, since part of a value may have been
+// written and nothing can safely follow it.
+struct Output<W: Write> {
+    out: W,
+    failed: bool,
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.failed || self.out.write_str(s).is_err() {
+            self.failed = true;
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// Builds a JSON document into a `fmt::Write`, for instance a `String` or a
+/// `SliceWriter`. Each call checks that the document stays well formed, and
+/// `finish()` that it is complete. Once a write has failed, every call fails
+/// with `Write`.
+pub struct JsonWriter<W: Write> {
+    out: Output<W>,
+    stack: NestingStack,
+    // Nothing has been written in the innermost container yet.
+    first: bool,
+    // A key has been written and its value is expected.
+    after_key: bool,
+    done: bool,
+    // The encoder of the binary string being written.
+    binary: Option<BinaryEncoder>,
+    // A string given in chunks is being written, `Some(true)` for a key.
+    chunked: Option<bool>,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(out: W) -> Self {
+        JsonWriter {
+            out: Output {
+                out: out,
+                failed: false,
+            },
+            stack: NestingStack::new(),
+            first: true,
+            after_key: false,
+            done: false,
+            binary: None,
+            chunked: None,
+        }
+    }
+
+    /// Returns the underlying writer once the document is complete.
+    pub fn finish(self) -> Result<W, JsonWriteError> {
+        self.check_output()?;
+        if !self.done {
+            return Err(JsonWriteError::Incomplete);
+        }
+        Ok(self.out.out)
+    }
+
+    fn check_output(&self) -> Result<(), JsonWriteError> {
+        if self.out.failed {
+            return Err(JsonWriteError::Write);
+        }
+        Ok(())
+    }
+
+    // Checks that no string given in chunks is being written.
+    fn check_unfinished(&self) -> Result<(), JsonWriteError> {
+        self.check_output()?;
+        if self.binary.is_some() {
+            return Err(JsonWriteError::UnfinishedBinary);
+        }
+        if self.chunked.is_some() {
+            return Err(JsonWriteError::UnfinishedString);
+        }
+        Ok(())
+    }
+
+    // Checks that a value can be written here, and writes the separator.
+    fn before_value(&mut self) -> Result<(), JsonWriteError> {
+        self.check_unfinished()?;
+        if self.done {
+            return Err(JsonWriteError::Complete);
+        }
+        if self.stack.depth > 0 {
+            if self.stack.in_object() {
+                if !self.after_key {
+                    return Err(JsonWriteError::ExpectedKey);
+                }
+                self.after_key = false;
+            } else if !self.first {
+                self.out.write_char(',')?;
+            }
+        }
+        self.first = false;
+        Ok(())
+    }
+
+    // Called once a complete value has been written.
+    fn value_done(&mut self) {
+        if self.stack.depth == 0 {
+            self.done = true;
+        }
+    }
+
+    fn begin(&mut self, object: bool) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        if self.stack.push(object).is_err() {
+            return Err(JsonWriteError::NestingTooDeep);
+        }
+        self.out.write_char(if object { '{' } else { '[' })?;
+        self.first = true;
+        Ok(())
+    }
+
+    fn end(&mut self, object: bool) -> Result<(), JsonWriteError> {
+        self.check_unfinished()?;
+        if self.stack.depth == 0 || self.stack.in_object() != object || self.after_key {
+            return Err(JsonWriteError::Unbalanced);
+        }
+        self.stack.pop();
+        self.out.write_char(if object { '}' } else { ']' })?;
+        self.first = false;
+        self.value_done();
+        Ok(())
+    }
+
+    pub fn begin_object(&mut self) -> Result<(), JsonWriteError> {
+        self.begin(true)
+    }
+
+    pub fn end_object(&mut self) -> Result<(), JsonWriteError> {
+        self.end(true)
+    }
+
+    pub fn begin_array(&mut self) -> Result<(), JsonWriteError> {
+        self.begin(false)
+    }
+
+    pub fn end_array(&mut self) -> Result<(), JsonWriteError> {
+        self.end(false)
+    }
+
+    // Checks that a key can be written here, and writes the separator.
+    fn before_key(&mut self) -> Result<(), JsonWriteError> {
+        self.check_unfinished()?;
+        if self.stack.depth == 0 || !self.stack.in_object() || self.after_key {
+            return Err(JsonWriteError::UnexpectedKey);
+        }
+        if !self.first {
+            self.out.write_char(',')?;
+        }
+        self.first = false;
+        Ok(())
+    }
+
+    /// Writes the key of the next object member.
+    pub fn key(&mut self, key: &str) -> Result<(), JsonWriteError> {
+        self.before_key()?;
+        write_escaped(&mut self.out, key)?;
+        self.out.write_char(':')?;
+        self.after_key = true;
+        Ok(())
+    }
+
+    /// Same as `key()`, for a key given in chunks like with `begin_string()`.
+    pub fn begin_key(&mut self) -> Result<(), JsonWriteError> {
+        self.before_key()?;
+        self.out.write_char('"')?;
+        self.chunked = Some(true);
+        Ok(())
+    }
+
+    /// Same as `key()`, for a property name read by a tokenizer.
+    pub fn json_key(&mut self, key: JsonStr) -> Result<(), JsonWriteError> {
+        self.before_key()?;
+        write_json_str(&mut self.out, key)?;
+        self.out.write_char(':')?;
+        self.after_key = true;
+        Ok(())
+    }
+
+    pub fn string(&mut self, value: &str) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        write_escaped(&mut self.out, value)?;
+        self.value_done();
+        Ok(())
+    }
+
+    /// Same as `string()`, for a string read by a tokenizer.
+    pub fn json_string(&mut self, value: JsonStr) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        write_json_str(&mut self.out, value)?;
+        self.value_done();
+        Ok(())
+    }
+
+    /// Starts a string given in chunks to `string_chunk()` and ended by
+    /// `end_string()`. Nothing else can be written until then.
+    pub fn begin_string(&mut self) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.out.write_char('"')?;
+        self.chunked = Some(false);
+        Ok(())
+    }
+
+    pub fn string_chunk(&mut self, value: &str) -> Result<(), JsonWriteError> {
+        self.check_output()?;
+        if self.chunked.is_none() {
+            return Err(JsonWriteError::Unbalanced);
+        }
+        write_escaped_text(&mut self.out, value)?;
+        Ok(())
+    }
+
+    pub fn end_string(&mut self) -> Result<(), JsonWriteError> {
+        self.check_output()?;
+        match self.chunked.take() {
+            Some(true) => {
+                self.out.write_str("\":")?;
+                self.after_key = true;
+            }
+            Some(false) => {
+                self.out.write_char('"')?;
+                self.value_done();
+            }
+            None => return Err(JsonWriteError::Unbalanced),
+        }
+        Ok(())
+    }
+
+    /// Writes `data` as a string in the given encoding.
+    pub fn binary(&mut self, data: &[u8], encoding: BinaryEncoding) -> Result<(), JsonWriteError> {
+        self.begin_binary(encoding)?;
+        self.binary_chunk(data)?;
+        self.end_binary()
+    }
+
+    /// Starts a string holding binary data, given in chunks to
+    /// `binary_chunk()` and ended by `end_binary()`. Nothing else can be
+    /// written until then.
+    pub fn begin_binary(&mut self, encoding: BinaryEncoding) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.out.write_char('"')?;
+        self.binary = Some(BinaryEncoder::new(encoding));
+        Ok(())
+    }
+
+    pub fn binary_chunk(&mut self, data: &[u8]) -> Result<(), JsonWriteError> {
+        self.check_output()?;
+        match self.binary {
+            Some(ref mut encoder) => encoder.write(&mut self.out, data)?,
+            None => return Err(JsonWriteError::Unbalanced),
+        }
+        Ok(())
+    }
+
+    pub fn end_binary(&mut self) -> Result<(), JsonWriteError> {
+        self.check_output()?;
+        match self.binary.take() {
+            Some(mut encoder) => encoder.finish(&mut self.out)?,
+            None => return Err(JsonWriteError::Unbalanced),
+        }
+        self.out.write_char('"')?;
+        self.value_done();
+        Ok(())
+    }
+
+    pub fn integer(&mut self, value: i64) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        write!(self.out, "{}", value)?;
+        self.value_done();
+        Ok(())
+    }
+
+    /// Writes a number, which always has a fractional part or an exponent
+    /// so that it is read back as a `JsonToken::Number`.
+    pub fn float(&mut self, value: f64) -> Result<(), JsonWriteError> {
+        if value != value || value == f64::INFINITY || value == f64::NEG_INFINITY {
+            return Err(JsonWriteError::InvalidNumber);
+        }
+        self.before_value()?;
+        // Without an exponent, 1e-300 would take 302 characters.
+        let tiny = value != 0.0 && value > -1e-6 && value < 1e-6;
+        if tiny || value <= -1e15 || value >= 1e15 {
+            write!(self.out, "{:e}", value)?;
+        } else if (value as i64) as f64 == value {
+            write!(self.out, "{:.1}", value)?;
+        } else {
+            write!(self.out, "{}", value)?;
+        }
+        self.value_done();
+        Ok(())
+    }
+
+    /// Writes a number given as text, like the one of a `RawNumber` token.
+    /// Fails with `InvalidNumber` if it isn't a JSON number.
+    pub fn json_number(&mut self, text: &str) -> Result<(), JsonWriteError> {
+        let mut tokenizer = JsonTokenizer::new(text).strict(true).raw_numbers(true);
+        match tokenizer.next_token() {
+            Ok(JsonToken::Integer(_)) |
+            Ok(JsonToken::RawNumber(_)) if tokenizer.next_token() == Ok(JsonToken::Done) => {}
+            _ => return Err(JsonWriteError::InvalidNumber),
+        }
+        self.before_value()?;
+        self.out.write_str(text)?;
+        self.value_done();
+        Ok(())
+    }
+
+    pub fn bool(&mut self, value: bool) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.out.write_str(if value { "true" } else { "false" })?;
+        self.value_done();
+        Ok(())
+    }
+
+    pub fn null(&mut self) -> Result<(), JsonWriteError> {
+        self.before_value()?;
+        self.out.write_str("null")?;
+        self.value_done();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use collections::String;
+    use json::{JsonStr, JsonToken, JsonTokenizer};
+    use writer::{JsonWriteError, JsonWriter, SliceWriter};
+
+    #[test]
+    fn round_trip() {
+        let mut writer = JsonWriter::new(String::new());
+        writer.begin_object().unwrap();
+        writer.key("name").unwrap();
+        writer.string("PM 2.5 \"fine\" \\ particulates\n\u{1}").unwrap();
+        writer.key("symbol").unwrap();
+        writer.string("μg/m³").unwrap();
+        writer.key("values").unwrap();
+        writer.begin_array().unwrap();
+        writer.integer(-42).unwrap();
+        writer.float(12.5).unwrap();
+        writer.float(3.0).unwrap();
+        writer.float(1.5e300).unwrap();
+        writer.float(-0.0000125).unwrap();
+        writer.float(1e-300).unwrap();
+        writer.float(0.0).unwrap();
+        writer.bool(true).unwrap();
+        writer.bool(false).unwrap();
+        writer.null().unwrap();
+        writer.begin_object().unwrap();
+        writer.end_object().unwrap();
+        writer.end_array().unwrap();
+        writer.end_object().unwrap();
+        let text = writer.finish().unwrap();
+
+        assert_eq!(text,
+                   "{\"name\":\"PM 2.5 \\\"fine\\\" \\\\ particulates\\n\\u0001\",\
+                    \"symbol\":\"μg/m³\",\
+                    \"values\":[-42,12.5,3.0,1.5e300,-0.0000125,1e-300,0.0,true,false,null,{}]}");
+
+        let name = r#"PM 2.5 \"fine\" \\ particulates\n\u0001"#;
+        let mut tokenizer = JsonTokenizer::new(&text).strict(true);
+        let expected = [JsonToken::StartObject,
+                        JsonToken::PropertyName(JsonStr::new("name")),
+                        JsonToken::String(JsonStr::new(name)),
+                        JsonToken::PropertyName(JsonStr::new("symbol")),
+                        JsonToken::String(JsonStr::new("μg/m³")),
+                        JsonToken::PropertyName(JsonStr::new("values")),
+                        JsonToken::StartArray,
+                        JsonToken::Integer(-42),
+                        JsonToken::Number(12.5),
+                        JsonToken::Number(3.0),
+                        JsonToken::Number(1.5e300),
+                        JsonToken::Number(-0.0000125),
+                        JsonToken::Number(1e-300),
+                        JsonToken::Number(0.0),
+                        JsonToken::True,
+                        JsonToken::False,
+                        JsonToken::Null,
+                        JsonToken::StartObject,
+                        JsonToken::EndObject,
+                        JsonToken::EndArray,
+                        JsonToken::EndObject,
+                        JsonToken::Done];
+
+        for i in 0..expected.len() {
+            assert_eq!(tokenizer.next_token().unwrap(), expected[i]);
+        }
+    }
+
+    #[test]
+    fn slice_writer() {
+        let mut buffer = [0u8; 16];
+        {
+            let mut writer = JsonWriter::new(SliceWriter::new(&mut buffer));
+            writer.begin_array().unwrap();
+            writer.string("0123456789").unwrap();
+            assert_eq!(writer.integer(12345), Err(JsonWriteError::Write));
+            // Only the `,` made it, nothing can follow it.
+            assert_eq!(writer.integer(1), Err(JsonWriteError::Write));
+            assert_eq!(writer.end_array(), Err(JsonWriteError::Write));
+            assert_eq!(writer.binary_chunk(&[]), Err(JsonWriteError::Write));
+            assert_eq!(writer.finish().err(), Some(JsonWriteError::Write));
+        }
+        assert_eq!(&buffer[..15], b"[\"0123456789\",\0");
+
+        let mut writer = JsonWriter::new(SliceWriter::new(&mut buffer));
+        writer.begin_array().unwrap();
+        writer.integer(1).unwrap();
+        writer.integer(2).unwrap();
+        writer.end_array().unwrap();
+        let out = writer.finish().unwrap();
+        assert_eq!(out.as_str(), "[1,2]");
+        assert_eq!(out.len(), 5);
+    }
+
+    #[test]
+    fn json_number() {
+        let mut writer = JsonWriter::new(String::new());
+        writer.begin_array().unwrap();
+        writer.json_number("1.50").unwrap();
+        writer.json_number("-2e-300").unwrap();
+        writer.json_number("18446744073709551616").unwrap();
+        for text in ["01", "1.", "1 2", "", "NaN", "[1]"].iter() {
+            assert_eq!(writer.json_number(text), Err(JsonWriteError::InvalidNumber));
+        }
+        writer.end_array().unwrap();
+        assert_eq!(writer.finish().unwrap(), "[1.50,-2e-300,18446744073709551616]");
+    }
+
+    #[test]
+    fn chunked_strings() {
+        let mut writer = JsonWriter::new(String::new());
+        writer.begin_object().unwrap();
+        writer.begin_key().unwrap();
+        writer.string_chunk("na").unwrap();
+        assert_eq!(writer.end_object(), Err(JsonWriteError::UnfinishedString));
+        writer.string_chunk("me").unwrap();
+        writer.end_string().unwrap();
+        writer.begin_string().unwrap();
+        writer.string_chunk("a\"").unwrap();
+        assert_eq!(writer.null(), Err(JsonWriteError::UnfinishedString));
+        writer.string_chunk("\nb").unwrap();
+        writer.end_string().unwrap();
+        assert_eq!(writer.end_string(), Err(JsonWriteError::Unbalanced));
+        assert_eq!(writer.string_chunk("c"), Err(JsonWriteError::Unbalanced));
+        writer.end_object().unwrap();
+        assert_eq!(writer.finish().unwrap(), "{\"name\":\"a\\\"\\nb\"}");
+    }
+
+    #[test]
+    fn structure_errors() {
+        let mut writer = JsonWriter::new(String::new());
+        assert_eq!(writer.key("a"), Err(JsonWriteError::UnexpectedKey));
+        assert_eq!(writer.end_object(), Err(JsonWriteError::Unbalanced));
+        writer.begin_object().unwrap();
+        assert_eq!(writer.integer(1), Err(JsonWriteError::ExpectedKey));
+        assert_eq!(writer.end_array(), Err(JsonWriteError::Unbalanced));
+        writer.key("a").unwrap();
+        assert_eq!(writer.key("b"), Err(JsonWriteError::UnexpectedKey));
+        assert_eq!(writer.end_object(), Err(JsonWriteError::Unbalanced));
+        writer.begin_array().unwrap();
+        assert_eq!(writer.key("c"), Err(JsonWriteError::UnexpectedKey));
+        assert_eq!(writer.float(0.0 / 0.0), Err(JsonWriteError::InvalidNumber));
+        writer.end_array().unwrap();
+        writer.end_object().unwrap();
+        assert_eq!(writer.null(), Err(JsonWriteError::Complete));
+        assert_eq!(writer.finish().unwrap(), "{\"a\":[]}");
+
+        let mut writer = JsonWriter::new(String::new());
+        writer.begin_array().unwrap();
+        assert_eq!(writer.finish().unwrap_err(), JsonWriteError::Incomplete);
+    }
+}
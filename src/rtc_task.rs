@@ -11,58 +11,110 @@ use cc3200::socket_channel::SocketChannel;
 use config;
 use core::str;
 use freertos_rs::{Duration, FreeRtosError, Task, Queue};
-use microjson::{JsonToken, JsonTokenizer};
+use microjson::{JsonDispatcher, JsonError, JsonLimits, JsonStr, JsonStreamTokenizer, JsonVisitor,
+                Visit};
 use MessageKind;
 use smallhttp::{Client, HttpHeader};
 use smallhttp::traits::Channel;
 
+fn log_json_error(err: JsonError) {
+    error!("Invalid response from {}: {}", config::RTC_URL, err);
+}
+
+// Looks for the "time" property of the response.
+struct TimeVisitor {
+    in_time: bool,
+    seconds: Option<i64>,
+}
+
+impl<'a> JsonVisitor<'a> for TimeVisitor {
+    fn on_key(&mut self, key: JsonStr<'a>) -> Visit {
+        info!("prop_name is {}", key);
+        self.in_time = key == "time";
+        if self.in_time {
+            Visit::Continue
+        } else {
+            Visit::Skip
+        }
+    }
+
+    fn on_integer(&mut self, seconds: i64) -> Visit {
+        if !self.in_time {
+            return Visit::Continue;
+        }
+        // Times before the epoch are as invalid as any other garbage.
+        if seconds < 0 {
+            error!("Invalid time from {}: {}", config::RTC_URL, seconds);
+        } else {
+            self.seconds = Some(seconds);
+        }
+        Visit::Stop
+    }
+}
+
 fn update_rtc() -> Result<(), ()> {
+    // SocketChannel is plain TCP, it would send the request to an https://
+    // server in clear text.
+    if config::RTC_URL.starts_with("https://") {
+        error!("Can't check time from {}: TLS is not supported yet", config::RTC_URL);
+        return Err(());
+    }
+    warn!("{} is not authenticated, the time received can't be trusted", config::RTC_URL);
     info!("Checking time from server at {}", config::RTC_URL);
 
     let start = RTC::get();
     let mut client = Client::new(SocketChannel::new().unwrap());
-    let response = client.get(config::RTC_URL)
+    let mut response = client.get(config::RTC_URL)
         .open()?
         .header(HttpHeader::Connection, "close")?
         .response(|_| false)?;
 
+    // We receive a json string like : {"time":1480556487,"isoDate":"2016-12-01T01:41:27Z"}
+    // The body is parsed as it arrives, so the buffer only has to hold the longest token.
     let mut buffer = [0u8; 128];
-    if let Ok(text) = response.body.read_string_to_end(&mut buffer) {
-        let end = RTC::get();
-        info!("Received response from {} in {}s : {}",
-              config::RTC_URL,
-              end - start,
-              text);
-        // We receive a json string like : {"time":1480556487,"isoDate":"2016-12-01T01:41:27Z"}
-        let mut tokenizer = JsonTokenizer::new(&text);
+    // The response is tiny, anything much bigger comes from a misbehaving server.
+    let limits = JsonLimits {
+        max_depth: 4,
+        max_string_len: 64,
+        max_number_len: 24,
+        max_tokens: 64,
+    };
+    let mut tokenizer = JsonStreamTokenizer::new(&mut buffer).limits(limits);
+    let mut dispatcher = JsonDispatcher::new();
+    let mut visitor = TimeVisitor {
+        in_time: false,
+        seconds: None,
+    };
+    let mut chunk = [0u8; 64];
+    while !dispatcher.finished() {
+        let len = match response.body.read(&mut chunk) {
+            Ok(len) => len,
+            Err(_) => {
+                error!("Failed to read answer from {}", config::RTC_URL);
+                return Err(());
+            }
+        };
+        if len == 0 {
+            tokenizer.finish();
+        }
+
+        let mut fed = 0;
         loop {
-            let token = tokenizer.next_token()?;
-            debug!("Token: |{:?}|", token);
-            match token {
-                JsonToken::PropertyName(prop_name) => {
-                    info!("prop_name is {}", prop_name);
-                    if prop_name == "time" {
-                        match tokenizer.next_token()? {
-                            JsonToken::Literal(value) => {
-                                info!("Setting RTC to {}", value);
-                                if let Ok(seconds) = value.parse::<u64>() {
-                                    RTC::set(seconds as i64);
-                                    break;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-                JsonToken::Done => {
-                    break;
-                }
-                _ => {}
+            fed += tokenizer.feed(&chunk[fed..len]);
+            let finished = dispatcher.run_stream(&mut tokenizer, &mut visitor)
+                .map_err(log_json_error)?;
+            // Keep going while the buffer is too full to take the rest of the chunk.
+            if finished || fed == len {
+                break;
             }
         }
-    } else {
-        error!("Failed to read answer from {}", config::RTC_URL);
-        return Err(());
+    }
+
+    if let Some(seconds) = visitor.seconds {
+        let end = RTC::get();
+        info!("Received time from {} in {}s", config::RTC_URL, end - start);
+        info!("Setting RTC to {}", seconds);
+        RTC::set(seconds);
     }
     Ok(())
 }